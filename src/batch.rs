@@ -0,0 +1,251 @@
+use crate::protocol::encode_request;
+use crate::{Encoder, Error, HttpClient};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+/// A batch of requests, sent with a single HTTP call
+///
+/// Results are returned in the order the calls were added, notifications
+/// produce no results.
+pub struct Batch<'a, C>
+where
+    C: Encoder,
+{
+    client: &'a HttpClient<C>,
+    requests: Vec<Vec<u8>>,
+    ids: Vec<u64>,
+}
+
+impl<'a, C> Batch<'a, C>
+where
+    C: Encoder,
+{
+    pub(crate) fn new(client: &'a HttpClient<C>) -> Self {
+        Self {
+            client,
+            requests: Vec::new(),
+            ids: Vec::new(),
+        }
+    }
+    /// Adds a call, returns the index of its result
+    pub fn add<P: Serialize>(&mut self, method: &str, params: P) -> Result<usize, Error> {
+        let id = self.client.next_id();
+        self.push(method, params, Some(id))?;
+        self.ids.push(id);
+        Ok(self.ids.len() - 1)
+    }
+    /// Adds a notification (a request without ID)
+    pub fn notify<P: Serialize>(&mut self, method: &str, params: P) -> Result<(), Error> {
+        self.push(method, params, None)
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+    fn push<P: Serialize>(
+        &mut self,
+        method: &str,
+        params: P,
+        id: Option<u64>,
+    ) -> Result<(), Error> {
+        self.client.check_params(method, Some(&params))?;
+        let mut buf = self.client.take_buffer();
+        encode_request(
+            &self.client.encoder,
+            &self.client.wire_method(method),
+            Some(params),
+            id,
+            &self.client.config.compat,
+            &mut buf,
+        )?;
+        self.requests.push(buf);
+        Ok(())
+    }
+    // entries are copied into the payload, their buffers are reused
+    fn payload(&mut self) -> Result<Vec<u8>, Error> {
+        let payload = self.client.encoder.encode_batch(&self.requests);
+        for buf in self.requests.drain(..) {
+            self.client.release_buffer(buf);
+        }
+        payload
+    }
+    pub fn call<R>(mut self) -> Result<Vec<Result<R, Error>>, Error>
+    where
+        R: DeserializeOwned,
+    {
+        if self.requests.is_empty() {
+            return Ok(Vec::new());
+        }
+        let payload = self.payload()?;
+        let http_request = self
+            .client
+            .http_request(self.client.target(), self.client.batch_request(payload))?;
+        self.client.execute(http_request, |_, buf| {
+            self.client.parse_batch_result(buf, &self.ids)
+        })
+    }
+    pub async fn call_async<R>(mut self) -> Result<Vec<Result<R, Error>>, Error>
+    where
+        R: DeserializeOwned,
+    {
+        if self.requests.is_empty() {
+            return Ok(Vec::new());
+        }
+        let payload = self.payload()?;
+        let http_request = self
+            .client
+            .http_request_async(self.client.target(), self.client.batch_request(payload))
+            .await?;
+        self.client
+            .execute_async(http_request, |_, buf| {
+                self.client.parse_batch_result(buf, &self.ids)
+            })
+            .await
+    }
+    /// Sends the batch, results of different types are extracted from the
+    /// response with [`BatchResponse::get`]
+    pub fn send(self) -> Result<BatchResponse, Error> {
+        self.call().map(BatchResponse::new)
+    }
+    pub async fn send_async(self) -> Result<BatchResponse, Error> {
+        self.call_async().await.map(BatchResponse::new)
+    }
+}
+
+/// Results of a batch, entries may succeed or fail independently
+///
+/// Results are kept decoded into self-describing values and converted into
+/// the requested types on extraction.
+#[derive(Debug)]
+pub struct BatchResponse {
+    results: Vec<Result<Value, Error>>,
+}
+
+impl BatchResponse {
+    fn new(results: Vec<Result<Value, Error>>) -> Self {
+        Self { results }
+    }
+    /// Extracts the result of the call with the index returned by
+    /// [`Batch::add`]
+    pub fn get<R: DeserializeOwned>(&self, index: usize) -> Result<R, Error> {
+        match self.results.get(index) {
+            Some(Ok(value)) => Ok(R::deserialize(value)?),
+            Some(Err(e)) => Err(entry_error(e)),
+            None => Err(Error::Protocol("no such batch entry")),
+        }
+    }
+    /// Failed calls with their indexes
+    pub fn errors(&self) -> impl Iterator<Item = (usize, &Error)> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter_map(|(i, r)| r.as_ref().err().map(|e| (i, e)))
+    }
+    /// True if all calls succeeded
+    #[inline]
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(Result::is_ok)
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+}
+
+// entries fail with RPC and protocol errors only, results are not decoded yet
+fn entry_error(err: &Error) -> Error {
+    match err {
+        Error::Rpc(e) => Error::Rpc(e.clone()),
+        Error::Protocol(s) => Error::Protocol(s),
+        _ => Error::Protocol("invalid batch entry"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_support::{serve, Response};
+    use crate::{http_client, Error};
+    use serde_json::{json, Value};
+
+    // replies to calls in the reverse order, the "fail" method fails
+    fn serve_batch() -> String {
+        serve(|req| {
+            let batch: Vec<Value> = serde_json::from_slice(&req.body).unwrap();
+            let replies: Vec<Value> = batch
+                .iter()
+                .rev()
+                .filter(|call| call.get("id").is_some())
+                .map(|call| {
+                    if call["method"] == "fail" {
+                        json!({
+                            "jsonrpc": "2.0",
+                            "id": call["id"],
+                            "error": { "code": -32000, "message": "failed" }
+                        })
+                    } else {
+                        json!({ "jsonrpc": "2.0", "id": call["id"], "result": call["params"] })
+                    }
+                })
+                .collect();
+            if replies.is_empty() {
+                Response::status(204)
+            } else {
+                Response::json(&Value::Array(replies))
+            }
+        })
+    }
+
+    #[test]
+    fn test_batch() {
+        let client = http_client(&serve_batch());
+        let mut batch = client.batch();
+        batch.notify("log", ["start"]).unwrap();
+        let first = batch.add("echo", [1]).unwrap();
+        batch.notify("log", ["middle"]).unwrap();
+        let second = batch.add("echo", ["two"]).unwrap();
+        let failed = batch.add("fail", ()).unwrap();
+        assert_eq!(batch.len(), 5);
+        let resp = batch.send().unwrap();
+        assert_eq!(resp.len(), 3);
+        assert_eq!(resp.get::<Vec<u8>>(first).unwrap(), [1]);
+        assert_eq!(resp.get::<Vec<String>>(second).unwrap(), ["two"]);
+        assert!(matches!(resp.get::<Value>(failed), Err(Error::Rpc(e)) if e.code() == -32000));
+        assert!(matches!(resp.get::<u8>(first), Err(Error::Decode(_))));
+        assert!(matches!(
+            resp.get::<Value>(3),
+            Err(Error::Protocol("no such batch entry"))
+        ));
+        assert!(!resp.is_success());
+        assert_eq!(resp.errors().map(|(i, _)| i).collect::<Vec<_>>(), [failed]);
+    }
+
+    #[test]
+    fn test_batch_notifications() {
+        let client = http_client(&serve_batch());
+        let mut batch = client.batch();
+        batch.notify("log", ["a"]).unwrap();
+        batch.notify("log", ["b"]).unwrap();
+        let resp = batch.send().unwrap();
+        assert!(resp.is_empty());
+        assert!(resp.is_success());
+        assert!(client.batch().call::<Value>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_batch_async() {
+        let client = http_client(&serve_batch());
+        let mut batch = client.batch();
+        let id = batch.add("echo", [5]).unwrap();
+        batch.notify("log", ["x"]).unwrap();
+        let results = futures_lite::future::block_on(batch.call_async::<Vec<u8>>()).unwrap();
+        assert_eq!(results[id].as_ref().unwrap(), &[5]);
+    }
+}
@@ -0,0 +1,431 @@
+//! Streams base64 results of JSON responses into writers, the response is
+//! decoded while it is read and not buffered
+use crate::depth;
+use crate::protocol::{Compat, Id, RpcError};
+use crate::{ChecksumHasher, Error};
+use futures_lite::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+const READ_BUF_SIZE: usize = 8192;
+const WRITE_BUF_SIZE: usize = 8192;
+// members other than the result are buffered, up to this size
+const MAX_MEMBER: usize = 64 * 1024;
+
+struct Reader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: AsyncRead + Unpin> Reader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::with_capacity(READ_BUF_SIZE),
+            pos: 0,
+        }
+    }
+    async fn peek(&mut self) -> Result<Option<u8>, Error> {
+        if self.pos == self.buf.len() {
+            self.buf.resize(READ_BUF_SIZE, 0);
+            let len = self
+                .reader
+                .read(&mut self.buf)
+                .await
+                .map_err(isahc::Error::from)?;
+            self.buf.truncate(len);
+            self.pos = 0;
+        }
+        Ok(self.buf.get(self.pos).copied())
+    }
+    async fn byte(&mut self) -> Result<u8, Error> {
+        let b = self
+            .peek()
+            .await?
+            .ok_or(Error::Protocol("unexpected end of response"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+    async fn non_ws(&mut self) -> Result<u8, Error> {
+        loop {
+            let b = self.byte().await?;
+            if !b.is_ascii_whitespace() {
+                return Ok(b);
+            }
+        }
+    }
+}
+
+/// Feeds the body read to a checksum hasher
+pub(crate) struct ChecksumReader<R> {
+    reader: R,
+    hasher: Option<Box<dyn ChecksumHasher>>,
+    len: u64,
+}
+
+impl<R> ChecksumReader<R> {
+    pub(crate) fn new(reader: R, hasher: Option<Box<dyn ChecksumHasher>>) -> Self {
+        Self {
+            reader,
+            hasher,
+            len: 0,
+        }
+    }
+    /// Reads the rest of the body and verifies the checksum
+    pub(crate) async fn verify(mut self, value: Option<&str>) -> Result<(), Error>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let Some(mut hasher) = self.hasher.take() else {
+            return Ok(());
+        };
+        // the rest is trailing whitespace of valid responses
+        let mut rest = Vec::new();
+        self.reader
+            .read_to_end(&mut rest)
+            .await
+            .map_err(isahc::Error::from)?;
+        hasher.update(&rest);
+        // empty responses, e.g. 204, have nothing to verify
+        if self.len == 0 && rest.is_empty() {
+            return Ok(());
+        }
+        let value = value.ok_or(Error::Integrity("no response checksum"))?;
+        if hasher.verify(value) {
+            Ok(())
+        } else {
+            Err(Error::Integrity("response checksum mismatch"))
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ChecksumReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = &mut *self;
+        let result = Pin::new(&mut this.reader).poll_read(cx, buf);
+        if let (Poll::Ready(Ok(len)), Some(hasher)) = (&result, this.hasher.as_mut()) {
+            hasher.update(&buf[..*len]);
+            this.len += *len as u64;
+        }
+        result
+    }
+}
+
+/// Writes the decoded result of a JSON response, returns the number of bytes
+/// written. The response members may come in any order
+pub(crate) async fn copy_result<R, W>(
+    reader: R,
+    writer: &mut W,
+    id: u64,
+    compat: &Compat,
+    max_depth: Option<usize>,
+) -> Result<u64, Error>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut reader = Reader::new(reader);
+    if reader.non_ws().await? != b'{' {
+        return Err(Error::Protocol("response is not an object"));
+    }
+    // members are nested into the response object
+    let member_depth = match max_depth {
+        Some(0) => return Err(Error::Protocol("response nesting too deep")),
+        Some(max_depth) => Some(max_depth - 1),
+        None => None,
+    };
+    let mut written = None;
+    let mut resp_id: Option<Id> = None;
+    let mut error: Option<RpcError> = None;
+    let mut jsonrpc: Option<String> = None;
+    let mut b = reader.non_ws().await?;
+    while b != b'}' {
+        if b != b'"' {
+            return Err(Error::Protocol("invalid response"));
+        }
+        let key = read_value(&mut reader, b).await?;
+        if reader.non_ws().await? != b':' {
+            return Err(Error::Protocol("invalid response"));
+        }
+        let first = reader.non_ws().await?;
+        match key.as_slice() {
+            b"\"result\"" if first == b'"' => {
+                written = Some(decode_base64(&mut reader, writer).await?);
+            }
+            key => {
+                let value = read_value(&mut reader, first).await?;
+                if member_depth.is_some_and(|max_depth| depth::json_exceeds(&value, max_depth)) {
+                    return Err(Error::Protocol("response nesting too deep"));
+                }
+                match key {
+                    b"\"result\"" if value != b"null" => {
+                        return Err(Error::Protocol("result is not a string"));
+                    }
+                    b"\"id\"" => resp_id = serde_json::from_slice(&value)?,
+                    b"\"error\"" => error = serde_json::from_slice(&value)?,
+                    b"\"jsonrpc\"" => jsonrpc = serde_json::from_slice(&value)?,
+                    _ => {}
+                }
+            }
+        }
+        b = match reader.non_ws().await? {
+            b',' => reader.non_ws().await?,
+            b'}' => b'}',
+            _ => return Err(Error::Protocol("invalid response")),
+        };
+    }
+    compat.check_version(jsonrpc.as_deref())?;
+    if !resp_id.is_some_and(|resp_id| resp_id.matches(id, compat)) {
+        return Err(Error::Protocol("invalid response ID"));
+    }
+    if let Some(err) = error {
+        return Err(Error::Rpc(err));
+    }
+    let written = written.ok_or(Error::Protocol("null result"))?;
+    writer.flush().await?;
+    Ok(written)
+}
+
+/// Reads a complete JSON value which starts with the byte given, including
+/// the quotes of strings
+async fn read_value<R: AsyncRead + Unpin>(
+    reader: &mut Reader<R>,
+    first: u8,
+) -> Result<Vec<u8>, Error> {
+    let mut value = vec![first];
+    let mut depth = usize::from(matches!(first, b'{' | b'['));
+    let mut in_string = first == b'"';
+    let mut escaped = false;
+    while in_string || depth > 0 {
+        let b = reader.byte().await?;
+        if value.len() == MAX_MEMBER {
+            return Err(Error::Protocol("response member too large"));
+        }
+        value.push(b);
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+        } else {
+            match b {
+                b'"' => in_string = true,
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+    if !matches!(first, b'"' | b'{' | b'[') {
+        // scalars end at a delimiter, which is left for the caller
+        while let Some(b) = reader.peek().await? {
+            if matches!(b, b',' | b'}' | b']') || b.is_ascii_whitespace() {
+                break;
+            }
+            if value.len() == MAX_MEMBER {
+                return Err(Error::Protocol("response member too large"));
+            }
+            value.push(b);
+            reader.pos += 1;
+        }
+    }
+    Ok(value)
+}
+
+/// Decodes a base64 string (the opening quote is consumed) into the writer
+async fn decode_base64<R, W>(reader: &mut Reader<R>, writer: &mut W) -> Result<u64, Error>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut out = Vec::with_capacity(WRITE_BUF_SIZE);
+    let mut written = 0;
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    loop {
+        let c = match reader.byte().await? {
+            b'"' => break,
+            // some encoders escape slashes, MIME base64 has line breaks
+            b'\\' => match reader.byte().await? {
+                b'/' => b'/',
+                b'n' | b'r' => continue,
+                _ => return Err(Error::Protocol("invalid base64 result")),
+            },
+            b'=' => continue,
+            c => c,
+        };
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return Err(Error::Protocol("invalid base64 result")),
+        };
+        acc = (acc << 6) | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            if out.len() == WRITE_BUF_SIZE {
+                writer.write_all(&out).await?;
+                written += out.len() as u64;
+                out.clear();
+            }
+        }
+    }
+    writer.write_all(&out).await?;
+    Ok(written + out.len() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{copy_result, ChecksumReader};
+    use crate::protocol::Compat;
+    use crate::test_support::{serve, Response, Sum32};
+    use crate::{Checksum, Encoder, Error, HttpClient, Json};
+    use futures_lite::future;
+    use serde::{Deserialize, Serialize};
+
+    fn copy(resp: &[u8], max_depth: Option<usize>) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+        let written = future::block_on(copy_result(
+            resp,
+            &mut out,
+            1,
+            &Compat::default(),
+            max_depth,
+        ))?;
+        assert_eq!(written, out.len() as u64);
+        Ok(out)
+    }
+
+    #[test]
+    fn test_copy() {
+        let resp = br#"{"jsonrpc":"2.0","id":1,"result":"aGVsbG8gd29ybGQ="}"#;
+        assert_eq!(copy(resp, None).unwrap(), b"hello world");
+        let resp = br#" { "jsonrpc" : "2.0" , "id" : 1 , "result" : "" } "#;
+        assert!(copy(resp, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_copy_escaped() {
+        // escaped slashes and MIME line breaks
+        let resp = br#"{"jsonrpc":"2.0","id":1,"result":"\/\/\/\/\r\naGVs\nbG8="}"#;
+        assert_eq!(copy(resp, None).unwrap(), b"\xff\xff\xffhello");
+        // url-safe alphabet
+        let resp = br#"{"jsonrpc":"2.0","id":1,"result":"__-_"}"#;
+        assert_eq!(copy(resp, None).unwrap(), b"\xff\xff\xbf");
+        let resp = br#"{"jsonrpc":"2.0","id":1,"result":"aGVs\tbG8="}"#;
+        assert!(matches!(copy(resp, None), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn test_copy_large() {
+        let mut resp = br#"{"jsonrpc":"2.0","id":1,"result":""#.to_vec();
+        resp.extend("////".repeat(10_000).as_bytes());
+        resp.extend(br#""}"#);
+        assert_eq!(copy(&resp, None).unwrap(), vec![0xff; 30_000]);
+    }
+
+    #[test]
+    fn test_copy_member_order() {
+        let resp = br#"{"result":"aGVsbG8=","extra":{"a":[1,"}"]},"id":1,"jsonrpc":"2.0"}"#;
+        assert_eq!(copy(resp, None).unwrap(), b"hello");
+        let resp = br#"{"result":"aGVsbG8=","jsonrpc":"2.0","id":2}"#;
+        assert!(matches!(copy(resp, None), Err(Error::Protocol(_))));
+        let resp = br#"{"result":"aGVsbG8=","jsonrpc":"2.0"}"#;
+        assert!(matches!(copy(resp, None), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn test_copy_error() {
+        let resp =
+            br#"{"result":null,"error":{"code":-32000,"message":"failed"},"id":1,"jsonrpc":"2.0"}"#;
+        let Err(Error::Rpc(e)) = copy(resp, None) else {
+            panic!("rpc error expected");
+        };
+        assert_eq!(e.code(), -32000);
+        let resp = br#"{"jsonrpc":"2.0","id":1,"result":null}"#;
+        assert!(matches!(copy(resp, None), Err(Error::Protocol(_))));
+        let resp = br#"{"jsonrpc":"2.0","id":1,"result":5}"#;
+        assert!(matches!(copy(resp, None), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn test_copy_truncated() {
+        let resp = br#"{"jsonrpc":"2.0","id":1,"result":"aGVsbG8gd29ybGQ="}"#;
+        for len in 0..resp.len() {
+            assert!(
+                matches!(copy(&resp[..len], None), Err(Error::Protocol(_))),
+                "{}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn test_copy_depth() {
+        let resp = br#"{"jsonrpc":"2.0","id":1,"result":"aGk=","extra":[[1]]}"#;
+        assert_eq!(copy(resp, Some(3)).unwrap(), b"hi");
+        assert!(matches!(copy(resp, Some(2)), Err(Error::Protocol(_))));
+        assert!(matches!(copy(resp, Some(0)), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn test_checksum_reader() {
+        let resp = br#"{"jsonrpc":"2.0","id":1,"result":"aGk="}  "#;
+        let sum = Sum32.compute(resp);
+        let verify = |value: &str| {
+            let mut reader = ChecksumReader::new(&resp[..], Sum32.hasher());
+            let mut out = Vec::new();
+            future::block_on(async {
+                copy_result(&mut reader, &mut out, 1, &Compat::default(), None).await?;
+                reader.verify(Some(value)).await
+            })
+        };
+        verify(&sum).unwrap();
+        assert!(matches!(verify(&"0".repeat(8)), Err(Error::Integrity(_))));
+        let reader = ChecksumReader::new(&b""[..], Sum32.hasher());
+        future::block_on(reader.verify(None)).unwrap();
+    }
+
+    // a JSON encoder of an application
+    #[derive(Default)]
+    struct AppJson(Json);
+
+    impl Encoder for AppJson {
+        fn encode<P: Serialize>(&self, payload: &P) -> Result<Vec<u8>, Error> {
+            self.0.encode(payload)
+        }
+        fn decode<'a, R: Deserialize<'a>>(&self, data: &'a [u8]) -> Result<R, Error> {
+            self.0.decode(data)
+        }
+        fn mime(&self) -> &str {
+            "application/json"
+        }
+        fn name(&self) -> &'static str {
+            "app-json"
+        }
+        fn streams_json(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_custom_encoder() {
+        let url = serve(|req| Response::result(req, "aGk=".into()));
+        let client = HttpClient::<AppJson>::new(&url);
+        let mut out = Vec::new();
+        let written = future::block_on(client.call_to_writer("get", (), &mut out)).unwrap();
+        assert_eq!(written, 2);
+        assert_eq!(out, b"hi");
+    }
+}
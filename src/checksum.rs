@@ -0,0 +1,59 @@
+/// Body checksum, sent with requests in a header and verified in responses
+///
+/// The crate ships no algorithms, implement the trait with a hashing crate,
+/// e.g. `sha2`:
+///
+/// ```rust,ignore
+/// use bma_jrpc::{Checksum, ChecksumHasher};
+/// use sha2::{Digest, Sha256};
+///
+/// struct Sha256Checksum;
+///
+/// impl Checksum for Sha256Checksum {
+///     fn header(&self) -> &'static str {
+///         "x-checksum-sha256"
+///     }
+///     fn compute(&self, body: &[u8]) -> String {
+///         hex::encode(Sha256::digest(body))
+///     }
+///     fn hasher(&self) -> Option<Box<dyn ChecksumHasher>> {
+///         Some(Box::new(Sha256Hasher(Sha256::new())))
+///     }
+/// }
+///
+/// struct Sha256Hasher(Sha256);
+///
+/// impl ChecksumHasher for Sha256Hasher {
+///     fn update(&mut self, data: &[u8]) {
+///         self.0.update(data);
+///     }
+///     fn verify(self: Box<Self>, value: &str) -> bool {
+///         hex::encode(self.0.finalize()).eq_ignore_ascii_case(value.trim())
+///     }
+/// }
+/// ```
+///
+/// Checksums which are not keyed detect corrupted bodies but not tampered
+/// ones, use TLS or a response validator to authenticate responses.
+pub trait Checksum: Send + Sync {
+    /// The header name, e.g. `x-checksum-sha256`
+    fn header(&self) -> &'static str;
+    fn compute(&self, body: &[u8]) -> String;
+    /// Checks the header value received with a body
+    fn verify(&self, body: &[u8], value: &str) -> bool {
+        self.compute(body) == value
+    }
+    /// An incremental checksum, required to verify streamed responses (see
+    /// [`HttpClient::call_to_writer`](crate::HttpClient::call_to_writer)).
+    /// Not supported by default
+    fn hasher(&self) -> Option<Box<dyn ChecksumHasher>> {
+        None
+    }
+}
+
+/// Computes a checksum of a body received in parts
+pub trait ChecksumHasher: Send {
+    fn update(&mut self, data: &[u8]);
+    /// Checks the header value received with the body
+    fn verify(self: Box<Self>, value: &str) -> bool;
+}
@@ -0,0 +1,94 @@
+use crate::timer::Delay;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A boxed sleep future returned by [`Clock::sleep_async`]
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Time source for retries, backoff, hedging and latency tracking
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+    fn sleep_async(&self, duration: Duration) -> Sleep;
+}
+
+/// The default clock, uses the system time and real sleeps
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+    #[inline]
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+    #[inline]
+    fn sleep_async(&self, duration: Duration) -> Sleep {
+        Box::pin(Delay::new(duration))
+    }
+}
+
+/// A manual clock for tests: sleeps return immediately and advance the time
+#[derive(Debug)]
+pub struct TestClock {
+    started: Instant,
+    state: Mutex<TestClockState>,
+}
+
+#[derive(Debug, Default)]
+struct TestClockState {
+    elapsed: Duration,
+    sleeps: Vec<Duration>,
+}
+
+impl Default for TestClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestClock {
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            state: <_>::default(),
+        }
+    }
+    /// Moves the time forward, e.g. to simulate call latency
+    #[inline]
+    pub fn advance(&self, duration: Duration) {
+        self.state.lock().unwrap().elapsed += duration;
+    }
+    /// The time passed since the clock has been created
+    #[inline]
+    pub fn elapsed(&self) -> Duration {
+        self.state.lock().unwrap().elapsed
+    }
+    /// Durations of all sleeps requested so far
+    #[inline]
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.state.lock().unwrap().sleeps.clone()
+    }
+}
+
+impl Clock for TestClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        self.started + self.elapsed()
+    }
+    fn sleep(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.elapsed += duration;
+        state.sleeps.push(duration);
+    }
+    fn sleep_async(&self, duration: Duration) -> Sleep {
+        self.sleep(duration);
+        Box::pin(std::future::ready(()))
+    }
+}
@@ -0,0 +1,171 @@
+//! Nesting depth scanners, run on responses before they are decoded
+
+/// Counts nested arrays and objects, strings are skipped
+pub(crate) fn json_exceeds(data: &[u8], max_depth: usize) -> bool {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for &b in data {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match b {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > max_depth {
+                    return true;
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Walks MessagePack markers, truncated data is left for the decoder
+#[cfg(feature = "msgpack")]
+pub(crate) fn msgpack_exceeds(data: &[u8], max_depth: usize) -> bool {
+    // the number of items left in each open container
+    let mut stack: Vec<u64> = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        while stack.last() == Some(&0) {
+            stack.pop();
+        }
+        if let Some(left) = stack.last_mut() {
+            *left -= 1;
+        }
+        let marker = data[pos];
+        pos += 1;
+        let (skip, items) = match marker {
+            0x00..=0x7f | 0xc0..=0xc3 | 0xe0..=0xff => (0, None),
+            0x80..=0x8f => (0, Some(u64::from(marker & 0x0f) * 2)),
+            0x90..=0x9f => (0, Some(u64::from(marker & 0x0f))),
+            0xa0..=0xbf => (usize::from(marker & 0x1f), None),
+            0xc4 | 0xd9 => (read_len(data, pos, 1).map_or(0, |len| 1 + len), None),
+            0xc5 | 0xda => (read_len(data, pos, 2).map_or(0, |len| 2 + len), None),
+            0xc6 | 0xdb => (read_len(data, pos, 4).map_or(0, |len| 4 + len), None),
+            0xc7 => (read_len(data, pos, 1).map_or(0, |len| 2 + len), None),
+            0xc8 => (read_len(data, pos, 2).map_or(0, |len| 3 + len), None),
+            0xc9 => (read_len(data, pos, 4).map_or(0, |len| 5 + len), None),
+            0xca | 0xce | 0xd2 => (4, None),
+            0xcb | 0xcf | 0xd3 => (8, None),
+            0xcc | 0xd0 => (1, None),
+            0xcd | 0xd1 => (2, None),
+            0xd4 => (2, None),
+            0xd5 => (3, None),
+            0xd6 => (5, None),
+            0xd7 => (9, None),
+            0xd8 => (17, None),
+            0xdc => (2, read_len(data, pos, 2).map(|len| len as u64)),
+            0xdd => (4, read_len(data, pos, 4).map(|len| len as u64)),
+            0xde => (2, read_len(data, pos, 2).map(|len| len as u64 * 2)),
+            0xdf => (4, read_len(data, pos, 4).map(|len| len as u64 * 2)),
+        };
+        pos = pos.saturating_add(skip);
+        if let Some(items) = items {
+            stack.push(items);
+            if stack.len() > max_depth {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(feature = "msgpack")]
+fn read_len(data: &[u8], pos: usize, size: usize) -> Option<usize> {
+    let bytes = data.get(pos..pos + size)?;
+    let len = bytes.iter().fold(0u64, |len, b| (len << 8) | u64::from(*b));
+    usize::try_from(len).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::json_exceeds;
+
+    #[test]
+    fn test_json() {
+        let data = br#"{"a":[1,{"b":[]}]}"#;
+        assert!(!json_exceeds(data, 4));
+        assert!(json_exceeds(data, 3));
+        assert!(!json_exceeds(b"[][][]", 1));
+        assert!(json_exceeds(b"[[]]", 1));
+        assert!(!json_exceeds(b"1", 0));
+    }
+
+    #[test]
+    fn test_json_strings() {
+        assert!(!json_exceeds(br#"["[[[{{{"]"#, 1));
+        assert!(!json_exceeds(br#"["\"[[[", "\\", "]]]"]"#, 1));
+        assert!(!json_exceeds(br#"["[\/[["]"#, 1));
+        assert!(json_exceeds(br#"["\\",[]]"#, 1));
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack() {
+        use super::msgpack_exceeds;
+        // {"a": [1, {"b": []}]}
+        let data = [0x81, 0xa1, b'a', 0x92, 0x01, 0x81, 0xa1, b'b', 0x90];
+        assert!(!msgpack_exceeds(&data, 4));
+        assert!(msgpack_exceeds(&data, 3));
+        // empty containers are closed at once
+        let data = [0x93, 0x90, 0x80, 0xdc, 0x00, 0x00];
+        assert!(!msgpack_exceeds(&data, 2));
+        assert!(msgpack_exceeds(&data, 1));
+        let data = [0x91, 0x91, 0x90];
+        assert!(!msgpack_exceeds(&data, 3));
+        assert!(msgpack_exceeds(&data, 2));
+        assert!(!msgpack_exceeds(&[0x01], 0));
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack_lengths() {
+        use super::msgpack_exceeds;
+        // payloads of strings, binaries and extensions look like arrays
+        let payload = [0x91; 3];
+        let mut data = vec![0x95];
+        data.extend([0xa3].iter().chain(&payload));
+        data.extend([0xd9, 0x03].iter().chain(&payload));
+        data.extend([0xda, 0x00, 0x03].iter().chain(&payload));
+        data.extend([0xc4, 0x03].iter().chain(&payload));
+        data.extend([0xc6, 0x00, 0x00, 0x00, 0x03].iter().chain(&payload));
+        assert!(!msgpack_exceeds(&data, 1));
+        let mut data = vec![0x94];
+        data.extend([0xc7, 0x03, 0x01].iter().chain(&payload));
+        data.extend([0xc8, 0x00, 0x03, 0x01].iter().chain(&payload));
+        data.extend([0xd5, 0x01, 0x91, 0x91]);
+        data.extend([0xd8, 0x01].iter().chain(&[0x91; 16]));
+        assert!(!msgpack_exceeds(&data, 1));
+        // numbers are skipped as well
+        let data = [
+            0x93, 0xcc, 0x91, 0xcd, 0x91, 0x91, 0xcb, 0x91, 0, 0, 0, 0, 0, 0, 0x91,
+        ];
+        assert!(!msgpack_exceeds(&data, 1));
+        // the array after the string is nested
+        let data = [0x92, 0xa1, b'x', 0x91, 0x90];
+        assert!(msgpack_exceeds(&data, 2));
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack_truncated() {
+        use super::msgpack_exceeds;
+        assert!(!msgpack_exceeds(&[0x91, 0xda, 0xff], 1));
+        assert!(!msgpack_exceeds(&[0x91, 0xdd, 0x00], 1));
+        assert!(!msgpack_exceeds(
+            &[0x91, 0xc6, 0xff, 0xff, 0xff, 0xff, 0x91],
+            1
+        ));
+    }
+}
@@ -0,0 +1,335 @@
+use crate::protocol::{Compat, Id, RpcError};
+use crate::Error;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use std::fmt;
+
+mod shape;
+
+pub use shape::FieldDiff;
+
+/// Details of a response which could not be decoded
+#[derive(Debug, Clone)]
+pub struct Diagnostics {
+    offset: usize,
+    path: String,
+    field: Option<String>,
+    message: String,
+    field_diff: Vec<FieldDiff>,
+}
+
+impl Diagnostics {
+    /// Byte offset in the response body where decoding failed
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    /// JSON path of the failed value, e.g. `$.result.items[2].name`
+    #[inline]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    /// The field which failed, if known
+    #[inline]
+    pub fn field(&self) -> Option<&str> {
+        self.field.as_deref()
+    }
+    #[inline]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+    /// Fields which the result type expects but the response has not and vice
+    /// versa, for each object of the result
+    #[inline]
+    pub fn field_diff(&self) -> &[FieldDiff] {
+        &self.field_diff
+    }
+    fn new(data: &[u8], base: usize, base_path: &str, err: &serde_json::Error) -> Self {
+        let local_offset = error_offset(data, err);
+        let (path, last_key) = json_path_at(data, local_offset);
+        let mut message = err.to_string();
+        // the position is reported as the offset
+        if let Some(pos) = message.rfind(" at line ") {
+            message.truncate(pos);
+        }
+        let field = quoted_field(&message).or(last_key);
+        Self {
+            offset: base + local_offset,
+            path: format!("{}{}", base_path, path),
+            field,
+            message,
+            field_diff: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {} (byte {})",
+            self.message, self.path, self.offset
+        )?;
+        for diff in &self.field_diff {
+            write!(f, "; {}", diff)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+#[derive(Deserialize)]
+struct RawResponse<'a> {
+    #[serde(default, borrow)]
+    jsonrpc: Option<&'a str>,
+    id: Id,
+    #[serde(borrow)]
+    result: Option<&'a RawValue>,
+    #[serde(borrow)]
+    error: Option<&'a RawValue>,
+}
+
+/// Parses a JSON response as
+/// [`protocol::parse_response`](crate::protocol::parse_response) does but
+/// decoding errors carry [`Diagnostics`]
+pub fn parse_response_verbose<R: DeserializeOwned>(
+    buf: &[u8],
+    id: u64,
+    compat: &Compat,
+) -> Result<R, Error> {
+    if buf.is_empty() {
+        return crate::protocol::parse_response(&crate::Json::default(), buf, id, compat);
+    }
+    let resp: RawResponse = serde_json::from_slice(buf)
+        .map_err(|e| Error::Decode(Box::new(Diagnostics::new(buf, 0, "$", &e))))?;
+    compat.check_version(resp.jsonrpc)?;
+    if !resp.id.matches(id, compat) {
+        return Err(Error::Protocol("invalid response ID"));
+    }
+    if let Some(raw) = resp.error {
+        let err: RpcError = decode_member(buf, raw, "$.error")?;
+        // a result member set as well is handled the same way
+        crate::protocol::parse_response::<_, IgnoredAny>(&crate::Json::default(), buf, id, compat)?;
+        Err(Error::Rpc(err))
+    } else if let Some(raw) = resp.result {
+        decode_member(buf, raw, "$.result")
+    } else {
+        // null members are handled the same way
+        crate::protocol::parse_response(&crate::Json::default(), buf, id, compat)
+    }
+}
+
+fn decode_member<R: DeserializeOwned>(buf: &[u8], raw: &RawValue, path: &str) -> Result<R, Error> {
+    let data = raw.get().as_bytes();
+    serde_json::from_slice(data).map_err(|e| {
+        // the raw value is borrowed from the buffer
+        let base = (data.as_ptr() as usize).saturating_sub(buf.as_ptr() as usize);
+        let mut diagnostics = Diagnostics::new(data, base, path, &e);
+        if let Ok(value) = serde_json::from_slice(data) {
+            diagnostics.field_diff = shape::field_diff::<R>(&value, path);
+        }
+        Error::Decode(Box::new(diagnostics))
+    })
+}
+
+fn error_offset(data: &[u8], err: &serde_json::Error) -> usize {
+    let mut line_start = 0;
+    for _ in 1..err.line() {
+        match data[line_start..].iter().position(|&b| b == b'\n') {
+            Some(pos) => line_start += pos + 1,
+            None => break,
+        }
+    }
+    (line_start + err.column().saturating_sub(1)).min(data.len())
+}
+
+// serde reports missing/unknown/duplicate fields as "... field `name` ..."
+fn quoted_field(message: &str) -> Option<String> {
+    if !message.contains(" field `") {
+        return None;
+    }
+    let start = message.find('`')? + 1;
+    let len = message[start..].find('`')?;
+    Some(message[start..start + len].to_owned())
+}
+
+enum Frame {
+    Object {
+        key: Option<String>,
+        expect_key: bool,
+    },
+    Array(usize),
+}
+
+/// Returns the JSON path of the value at the offset and the innermost key
+fn json_path_at(data: &[u8], offset: usize) -> (String, Option<String>) {
+    let mut stack: Vec<Frame> = Vec::new();
+    // errors reported at a closing bracket refer to the container itself
+    let end = match data.get(offset) {
+        Some(b'}' | b']') => offset + 1,
+        _ => offset.min(data.len()),
+    };
+    let mut i = 0;
+    while i < end {
+        match data[i] {
+            b'"' => {
+                let start = i + 1;
+                i += 1;
+                while i < data.len() && data[i] != b'"' {
+                    if data[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                if let Some(Frame::Object { key, expect_key }) = stack.last_mut() {
+                    if *expect_key {
+                        let end = i.min(data.len());
+                        *key = Some(String::from_utf8_lossy(&data[start..end]).into_owned());
+                        *expect_key = false;
+                    }
+                }
+            }
+            b'{' => stack.push(Frame::Object {
+                key: None,
+                expect_key: true,
+            }),
+            b'[' => stack.push(Frame::Array(0)),
+            b'}' | b']' => {
+                stack.pop();
+            }
+            b',' => match stack.last_mut() {
+                Some(Frame::Array(n)) => *n += 1,
+                Some(Frame::Object { key, expect_key }) => {
+                    *key = None;
+                    *expect_key = true;
+                }
+                None => {}
+            },
+            _ => {}
+        }
+        i += 1;
+    }
+    let mut path = String::new();
+    let mut last_key = None;
+    for frame in stack {
+        match frame {
+            Frame::Object { key: Some(key), .. } => {
+                path.push('.');
+                path.push_str(&key);
+                last_key = Some(key);
+            }
+            Frame::Object { key: None, .. } => {}
+            Frame::Array(n) => {
+                path.push_str(&format!("[{}]", n));
+                last_key = None;
+            }
+        }
+    }
+    (path, last_key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{json_path_at, parse_response_verbose, quoted_field, Diagnostics};
+    use crate::protocol::Compat;
+    use crate::test_support::{serve, Response};
+    use crate::{http_client, Error, Rpc};
+    use serde::Deserialize;
+
+    fn path_at(data: &str, pattern: &str) -> (String, Option<String>) {
+        json_path_at(data.as_bytes(), data.find(pattern).unwrap())
+    }
+
+    fn diagnostics<R: std::fmt::Debug>(result: Result<R, Error>) -> Diagnostics {
+        match result {
+            Err(Error::Decode(e)) => e.downcast_ref::<Diagnostics>().unwrap().clone(),
+            other => panic!("decode error expected: {:?}", other),
+        }
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Item {
+        name: String,
+        size: u32,
+    }
+
+    #[test]
+    fn test_json_path_at() {
+        let data = r#"{"a":{"b":[1,{"c":"x,]}"},3]},"d":true}"#;
+        assert_eq!(path_at(data, "1,"), (".a.b[0]".to_owned(), None));
+        assert_eq!(
+            path_at(data, r#""x"#),
+            (".a.b[1].c".to_owned(), Some("c".to_owned()))
+        );
+        // brackets in strings are skipped
+        assert_eq!(path_at(data, "3]"), (".a.b[2]".to_owned(), None));
+        assert_eq!(
+            path_at(data, "true"),
+            (".d".to_owned(), Some("d".to_owned()))
+        );
+        // an error at a closing bracket refers to the container
+        assert_eq!(
+            path_at(data, "]},"),
+            (".a.b".to_owned(), Some("b".to_owned()))
+        );
+        assert_eq!(
+            path_at(r#"{"k\"q":1}"#, "1"),
+            (r#".k\"q"#.to_owned(), Some(r#"k\"q"#.to_owned()))
+        );
+        assert_eq!(json_path_at(b"[1,2", 100), ("[1]".to_owned(), None));
+    }
+
+    #[test]
+    fn test_quoted_field() {
+        assert_eq!(
+            quoted_field("missing field `size`").as_deref(),
+            Some("size")
+        );
+        assert_eq!(quoted_field("invalid type: `x`"), None);
+    }
+
+    #[test]
+    fn test_parse_verbose() {
+        let resp =
+            r#"{"jsonrpc":"2.0","id":1,"result":[{"name":"a","size":1},{"name":"b","size":"2"}]}"#;
+        let diag = diagnostics(parse_response_verbose::<Vec<Item>>(
+            resp.as_bytes(),
+            1,
+            &Compat::new(),
+        ));
+        assert_eq!(diag.path(), "$.result[1].size");
+        assert_eq!(diag.field(), Some("size"));
+        // serde reports the end of the value
+        assert!(resp[..=diag.offset()].ends_with(r#""size":"2""#));
+        assert!(diag.message().starts_with("invalid type"), "{}", diag);
+        let resp = r#"{"jsonrpc":"2.0","id":1,"result":{"name":"a"}}"#;
+        let diag = diagnostics(parse_response_verbose::<Item>(
+            resp.as_bytes(),
+            1,
+            &Compat::new(),
+        ));
+        assert_eq!(diag.path(), "$.result");
+        assert_eq!(diag.field(), Some("size"));
+        // not decoding errors are the same as of the regular parser
+        let resp = r#"{"jsonrpc":"2.0","id":2,"result":1}"#;
+        assert!(matches!(
+            parse_response_verbose::<u8>(resp.as_bytes(), 1, &Compat::new()),
+            Err(Error::Protocol("invalid response ID"))
+        ));
+        let resp = r#"{"jsonrpc":"2.0","id":1,"error":{"code":-1,"message":"x"}}"#;
+        assert!(matches!(
+            parse_response_verbose::<u8>(resp.as_bytes(), 1, &Compat::new()),
+            Err(Error::Rpc(_))
+        ));
+    }
+
+    #[test]
+    fn test_client() {
+        let url = serve(|req| Response::result(req, serde_json::json!({ "name": 1 })));
+        let err = http_client(&url).call::<_, Item>("get", ()).unwrap_err();
+        assert_eq!(err.diagnostics().unwrap().path(), "$.result.name");
+    }
+}
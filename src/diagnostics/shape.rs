@@ -0,0 +1,288 @@
+//! Collects the fields expected by a type and the keys present in a JSON
+//! value, by deserializing the type from the value with a tracking
+//! deserializer
+use serde::de::{
+    self, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+};
+use serde::forward_to_deserialize_any;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::fmt;
+
+/// Fields missing in / unknown to the expected type at a JSON path
+#[derive(Debug, Clone)]
+pub struct FieldDiff {
+    path: String,
+    missing: Vec<&'static str>,
+    extra: Vec<String>,
+}
+
+impl FieldDiff {
+    #[inline]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    /// Fields of the type absent in the response (may be optional)
+    #[inline]
+    pub fn missing(&self) -> &[&'static str] {
+        &self.missing
+    }
+    /// Response fields the type does not have
+    #[inline]
+    pub fn extra(&self) -> &[String] {
+        &self.extra
+    }
+}
+
+impl fmt::Display for FieldDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.path)?;
+        if !self.missing.is_empty() {
+            write!(f, " missing {}", self.missing.join(", "))?;
+        }
+        if !self.extra.is_empty() {
+            write!(f, " extra {}", self.extra.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn field_diff<'de, R: de::Deserialize<'de>>(
+    value: &'de Value,
+    path: &str,
+) -> Vec<FieldDiff> {
+    let diffs = RefCell::new(Vec::new());
+    let _r = R::deserialize(Tracked {
+        value,
+        path: path.to_owned(),
+        diffs: &diffs,
+    });
+    diffs.into_inner()
+}
+
+struct Tracked<'de, 'b> {
+    value: &'de Value,
+    path: String,
+    diffs: &'b RefCell<Vec<FieldDiff>>,
+}
+
+impl<'de, 'b> Tracked<'de, 'b> {
+    fn child(&self, value: &'de Value, path: String) -> Self {
+        Self {
+            value,
+            path,
+            diffs: self.diffs,
+        }
+    }
+}
+
+impl<'de, 'b> Deserializer<'de> for Tracked<'de, 'b> {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::Object(map) => visitor.visit_map(TrackedMap {
+                iter: map.iter(),
+                value: None,
+                parent: self,
+            }),
+            Value::Array(values) => visitor.visit_seq(TrackedSeq {
+                iter: values.iter().enumerate(),
+                parent: self,
+            }),
+            value => value.deserialize_any(visitor),
+        }
+    }
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        if let Value::Object(map) = self.value {
+            record_diff(&self, map, fields);
+        }
+        self.deserialize_any(visitor)
+    }
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.value.is_null() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.value.deserialize_enum(name, variants, visitor)
+    }
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.value.deserialize_bytes(visitor)
+    }
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.value.deserialize_byte_buf(visitor)
+    }
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        unit unit_struct seq tuple tuple_struct map identifier ignored_any
+    }
+}
+
+fn record_diff(tracked: &Tracked, map: &Map<String, Value>, fields: &'static [&'static str]) {
+    let missing: Vec<&'static str> = fields
+        .iter()
+        .filter(|f| !map.contains_key(**f))
+        .copied()
+        .collect();
+    let extra: Vec<String> = map
+        .keys()
+        .filter(|k| !fields.contains(&k.as_str()))
+        .cloned()
+        .collect();
+    if !missing.is_empty() || !extra.is_empty() {
+        tracked.diffs.borrow_mut().push(FieldDiff {
+            path: tracked.path.clone(),
+            missing,
+            extra,
+        });
+    }
+}
+
+struct TrackedMap<'de, 'b> {
+    iter: serde_json::map::Iter<'de>,
+    value: Option<(&'de String, &'de Value)>,
+    parent: Tracked<'de, 'b>,
+}
+
+impl<'de, 'b> MapAccess<'de> for TrackedMap<'de, 'b> {
+    type Error = serde_json::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some((key, value));
+                seed.deserialize(key.as_str().into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let (key, value) = self
+            .value
+            .take()
+            .ok_or_else(|| de::Error::custom("value is missing"))?;
+        let path = format!("{}.{}", self.parent.path, key);
+        seed.deserialize(self.parent.child(value, path))
+    }
+}
+
+struct TrackedSeq<'de, 'b> {
+    iter: std::iter::Enumerate<std::slice::Iter<'de, Value>>,
+    parent: Tracked<'de, 'b>,
+}
+
+impl<'de, 'b> SeqAccess<'de> for TrackedSeq<'de, 'b> {
+    type Error = serde_json::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        match self.iter.next() {
+            Some((i, value)) => {
+                let path = format!("{}[{}]", self.parent.path, i);
+                seed.deserialize(self.parent.child(value, path)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::field_diff;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Order {
+        id: u64,
+        note: Option<String>,
+        items: Vec<Item>,
+        owner: Owner,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Item {
+        name: String,
+        size: u32,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Owner(Item);
+
+    fn diffs(value: &serde_json::Value) -> Vec<String> {
+        field_diff::<Order>(value, "$.result")
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_field_diff() {
+        let value = json!({
+            "id": 1,
+            "items": [{ "name": "a", "size": 1 }, { "name": "b", "weight": 2 }],
+            "owner": { "name": "c", "size": 3 },
+            "total": 10
+        });
+        assert_eq!(
+            diffs(&value),
+            [
+                "$.result: missing note extra total",
+                "$.result.items[1]: missing size extra weight"
+            ]
+        );
+        // objects are compared before their fields are decoded
+        let diff = &field_diff::<Order>(&value, "$.result")[0];
+        assert_eq!(diff.path(), "$.result");
+        assert_eq!(diff.missing(), ["note"]);
+        assert_eq!(diff.extra(), ["total"]);
+    }
+
+    #[test]
+    fn test_field_diff_nested() {
+        // newtypes and options are looked through
+        let value = json!({
+            "id": 1,
+            "note": "x",
+            "items": [],
+            "owner": { "name": "c" }
+        });
+        assert_eq!(diffs(&value), ["$.result.owner: missing size"]);
+        let value =
+            json!({ "id": 1, "note": null, "items": [], "owner": { "name": "c", "size": 1 } });
+        assert!(diffs(&value).is_empty());
+        // not an object
+        assert!(diffs(&json!([1, 2])).is_empty());
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+// events are dropped for subscribers which do not keep up
+const SUBSCRIBER_CAPACITY: usize = 1024;
+
+/// Client health events, see [`HttpClient::events`](crate::HttpClient::events)
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+#[allow(clippy::module_name_repetitions)]
+pub enum ClientEvent {
+    /// A call failed and is going to be retried after the delay
+    Retry {
+        method: String,
+        attempt: u32,
+        delay: Duration,
+        error: String,
+    },
+    /// A call was not retried as the retry budget is exhausted
+    RetryBudgetExhausted { method: String },
+    /// A hedged request was sent to the secondary endpoint
+    Hedged { method: String, url: String },
+    /// A call took longer than the slow call threshold
+    SlowCall { method: String, latency: Duration },
+}
+
+#[derive(Default)]
+pub(crate) struct Events {
+    subscribers: Mutex<Vec<async_channel::Sender<ClientEvent>>>,
+}
+
+impl Events {
+    pub(crate) fn subscribe(&self) -> async_channel::Receiver<ClientEvent> {
+        let (tx, rx) = async_channel::bounded(SUBSCRIBER_CAPACITY);
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+    /// The event is built only if there are subscribers
+    pub(crate) fn emit<F: FnOnce() -> ClientEvent>(&self, event: F) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        let event = event();
+        subscribers.retain(|tx| {
+            !matches!(
+                tx.try_send(event.clone()),
+                Err(async_channel::TrySendError::Closed(_))
+            )
+        });
+    }
+}
@@ -0,0 +1,56 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// A typed map of values carried through the call pipeline, e.g. tenant IDs,
+/// auth scopes or routing hints, one value per type
+///
+/// Extensions of a client are visible in all its calls, extensions of
+/// [`CallOpts`](crate::CallOpts) override them for a single call.
+#[derive(Clone, Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Inserts a value, replacing the previous one of the same type
+    #[inline]
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.map.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+    #[inline]
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+    /// Removes a value, returns true if it was set
+    #[inline]
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> bool {
+        self.map.remove(&TypeId::of::<T>()).is_some()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+    /// Values of the other map override the ones of the same type
+    pub(crate) fn merged(&self, other: &Extensions) -> Extensions {
+        if other.is_empty() {
+            return self.clone();
+        }
+        let mut map = self.map.clone();
+        map.extend(other.map.iter().map(|(k, v)| (*k, v.clone())));
+        Extensions { map }
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{Error, Rpc, RpcError};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Serialize;
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Wraps a client and injects faults into calls with the given probabilities
+/// (0.0..=1.0)
+///
+/// The random generator is seeded, so the same seed and the same sequence of
+/// calls produce the same faults.
+pub struct FaultInjector<T: Rpc> {
+    client: T,
+    rng: Mutex<SplitMix64>,
+    delay: Option<(f64, Duration)>,
+    drop: f64,
+    error: Option<(f64, i16)>,
+    duplicate: f64,
+    corrupt: f64,
+}
+
+impl<T: Rpc> FaultInjector<T> {
+    #[inline]
+    pub fn new(client: T, seed: u64) -> Self {
+        Self {
+            client,
+            rng: Mutex::new(SplitMix64(seed)),
+            delay: None,
+            drop: 0.0,
+            error: None,
+            duplicate: 0.0,
+            corrupt: 0.0,
+        }
+    }
+    /// Delays calls before sending
+    #[inline]
+    pub fn delay(mut self, probability: f64, delay: Duration) -> Self {
+        self.delay = Some((probability, delay));
+        self
+    }
+    /// Drops calls, the caller gets a timeout error
+    #[inline]
+    pub fn drop(mut self, probability: f64) -> Self {
+        self.drop = probability;
+        self
+    }
+    /// Replies with an RPC error with the code instead of calling the server
+    #[inline]
+    pub fn error(mut self, probability: f64, code: i16) -> Self {
+        self.error = Some((probability, code));
+        self
+    }
+    /// Sends calls twice, the result of the second one is returned
+    #[inline]
+    pub fn duplicate(mut self, probability: f64) -> Self {
+        self.duplicate = probability;
+        self
+    }
+    /// Calls the server but replaces the response with an undecodable one
+    #[inline]
+    pub fn corrupt(mut self, probability: f64) -> Self {
+        self.corrupt = probability;
+        self
+    }
+    fn roll(&self, probability: f64) -> bool {
+        probability > 0.0 && self.rng.lock().unwrap().next_f64() < probability
+    }
+}
+
+impl<T: Rpc> Rpc for FaultInjector<T> {
+    fn call<P, R>(&self, method: &str, params: P) -> Result<R, Error>
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
+        if let Some((probability, delay)) = self.delay {
+            if self.roll(probability) {
+                std::thread::sleep(delay);
+            }
+        }
+        if self.roll(self.drop) {
+            return Err(Error::Transport(
+                io::Error::new(io::ErrorKind::TimedOut, "injected fault").into(),
+            ));
+        }
+        if let Some((probability, code)) = self.error {
+            if self.roll(probability) {
+                return Err(Error::Rpc(RpcError::new(
+                    code,
+                    Some("injected fault".to_owned()),
+                )));
+            }
+        }
+        let result: R = if self.roll(self.duplicate) {
+            let params = serde_json::to_value(params).map_err(|e| Error::Encode(e.into()))?;
+            let _r: Result<IgnoredAny, Error> = self.client.call(method, &params);
+            self.client.call(method, params)?
+        } else {
+            self.client.call(method, params)?
+        };
+        if self.roll(self.corrupt) {
+            return Err(serde_json::from_slice::<IgnoredAny>(b"{\"jsonrpc\":")
+                .expect_err("corrupted payload decoded")
+                .into());
+        }
+        Ok(result)
+    }
+}
+
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    #[allow(clippy::cast_precision_loss)]
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FaultInjector;
+    use crate::{Error, ErrorKind, Rpc};
+    use serde::{de::DeserializeOwned, Serialize};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // returns the number of the call
+    #[derive(Default)]
+    struct Counter(AtomicUsize);
+
+    impl Rpc for Counter {
+        fn call<P: Serialize, R: DeserializeOwned>(
+            &self,
+            _method: &str,
+            _params: P,
+        ) -> Result<R, Error> {
+            let n = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(R::deserialize(serde_json::Value::from(n))?)
+        }
+    }
+
+    fn faults(injector: &FaultInjector<Counter>) -> Vec<bool> {
+        (0..64)
+            .map(|_| injector.call::<_, usize>("test", ()).is_err())
+            .collect()
+    }
+
+    #[test]
+    fn test_seed() {
+        let dropped = faults(&FaultInjector::new(Counter::default(), 42).drop(0.5));
+        assert_eq!(
+            dropped,
+            faults(&FaultInjector::new(Counter::default(), 42).drop(0.5))
+        );
+        assert_ne!(
+            dropped,
+            faults(&FaultInjector::new(Counter::default(), 7).drop(0.5))
+        );
+        assert!(dropped.iter().any(|d| *d) && dropped.iter().any(|d| !*d));
+        assert!(!faults(&FaultInjector::new(Counter::default(), 42))
+            .into_iter()
+            .any(|d| d));
+    }
+
+    #[test]
+    fn test_drop() {
+        let injector = FaultInjector::new(Counter::default(), 1).drop(1.0);
+        assert!(injector
+            .call::<_, usize>("test", ())
+            .unwrap_err()
+            .is_timeout());
+        assert_eq!(injector.client.0.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_error() {
+        let injector = FaultInjector::new(Counter::default(), 1).error(1.0, -32050);
+        let err = injector.call::<_, usize>("test", ()).unwrap_err();
+        assert!(matches!(err, Error::Rpc(e) if e.code() == -32050));
+        assert_eq!(injector.client.0.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let injector = FaultInjector::new(Counter::default(), 1).corrupt(1.0);
+        let err = injector.call::<_, usize>("test", ()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Decode);
+        assert_eq!(injector.client.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_duplicate() {
+        let injector = FaultInjector::new(Counter::default(), 1).duplicate(1.0);
+        assert_eq!(injector.call::<_, usize>("test", ()).unwrap(), 2);
+        assert_eq!(injector.client.0.load(Ordering::SeqCst), 2);
+    }
+}
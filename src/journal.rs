@@ -0,0 +1,445 @@
+use crate::retry::is_transient;
+use crate::{Encoder, Error, HttpClient};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Store-and-forward delivery of notifications and idempotent calls
+///
+/// Requests which can not be delivered because the server is unreachable
+/// (transport errors, HTTP 502, 503 and 504) are appended to a journal file
+/// and sent again by [`Journal::flush`], in order. While the journal is not
+/// empty, new requests are queued after the pending ones. Results of calls are
+/// ignored, an RPC error means the request was delivered.
+///
+/// Other errors, e.g. HTTP 401 of an expired credential, stop the delivery:
+/// the error is returned and pending requests are kept. A new request which
+/// fails this way with no pending ones is not queued.
+///
+/// Requests are sent by one thread at a time. Requests made while another
+/// thread delivers are queued without waiting.
+///
+/// The journal is stored as JSON lines.
+pub struct Journal<C: Encoder> {
+    client: HttpClient<C>,
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+struct State {
+    pending: VecDeque<Entry>,
+    // a thread delivers requests
+    flushing: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Entry {
+    method: String,
+    params: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(default)]
+    notification: bool,
+}
+
+impl<C: Encoder> Journal<C> {
+    /// Opens the journal file, pending requests are loaded if it exists. An
+    /// incomplete last line, left by a crash while appending, is truncated
+    pub fn open<P: AsRef<Path>>(client: HttpClient<C>, path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_owned();
+        let pending = match fs::read(&path) {
+            Ok(data) => {
+                let complete = data
+                    .iter()
+                    .rposition(|&b| b == b'\n')
+                    .map_or(0, |pos| pos + 1);
+                if complete < data.len() {
+                    fs::OpenOptions::new()
+                        .write(true)
+                        .open(&path)?
+                        .set_len(complete as u64)?;
+                }
+                data[..complete]
+                    .split(|&b| b == b'\n')
+                    .filter(|line| !line.is_empty())
+                    .map(serde_json::from_slice)
+                    .collect::<Result<_, _>>()?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            client,
+            path,
+            state: Mutex::new(State {
+                pending,
+                flushing: false,
+            }),
+        })
+    }
+    /// Sends a notification or queues it. A request with the dedup key of a
+    /// pending one is dropped
+    pub fn notify<P: Serialize>(
+        &self,
+        method: &str,
+        params: P,
+        key: Option<&str>,
+    ) -> Result<(), Error> {
+        self.send(Entry::new(method, params, key, true)?)
+    }
+    /// Calls an idempotent method or queues the call, the result is ignored. A
+    /// request with the dedup key of a pending one is dropped
+    pub fn call<P: Serialize>(
+        &self,
+        method: &str,
+        params: P,
+        key: Option<&str>,
+    ) -> Result<(), Error> {
+        self.send(Entry::new(method, params, key, false)?)
+    }
+    /// Sends pending requests in order, stops at the first one which can not
+    /// be delivered. Returns the number of requests sent, zero if another
+    /// thread delivers requests
+    pub fn flush(&self) -> Result<usize, Error> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.flushing || state.pending.is_empty() {
+                return Ok(0);
+            }
+            state.flushing = true;
+        }
+        self.flush_pending()
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().pending.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().unwrap().pending.is_empty()
+    }
+    #[inline]
+    pub fn client(&self) -> &HttpClient<C> {
+        &self.client
+    }
+    fn send(&self, entry: Entry) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        if entry.key.is_some() && state.pending.iter().any(|e| e.key == entry.key) {
+            return Ok(());
+        }
+        if state.flushing || !state.pending.is_empty() {
+            self.append(&entry)?;
+            state.pending.push_back(entry);
+            if state.flushing {
+                // delivered by the running flush
+                return Ok(());
+            }
+            state.flushing = true;
+            drop(state);
+            return self.flush_pending().map(|_| ());
+        }
+        state.flushing = true;
+        drop(state);
+        let result = delivered(self.deliver(&entry));
+        let mut state = self.state.lock().unwrap();
+        match result {
+            Ok(true) if !state.pending.is_empty() => {
+                // queued while the request has been sent
+                drop(state);
+                self.flush_pending().map(|_| ())
+            }
+            Ok(true) => {
+                state.flushing = false;
+                Ok(())
+            }
+            Ok(false) => {
+                state.flushing = false;
+                // the request goes before ones queued while it has been sent
+                state.pending.push_front(entry);
+                let result = if state.pending.len() == 1 {
+                    self.append(&state.pending[0])
+                } else {
+                    self.rewrite(&state.pending)
+                };
+                if result.is_err() {
+                    state.pending.pop_front();
+                }
+                result
+            }
+            Err(e) => {
+                state.flushing = false;
+                Err(e)
+            }
+        }
+    }
+    /// Delivers pending requests, the caller sets the flushing flag
+    fn flush_pending(&self) -> Result<usize, Error> {
+        let mut sent = 0;
+        let result = loop {
+            let Some(entry) = self.state.lock().unwrap().pending.front().cloned() else {
+                break Ok(());
+            };
+            match delivered(self.deliver(&entry)) {
+                Ok(true) => {
+                    self.state.lock().unwrap().pending.pop_front();
+                    sent += 1;
+                }
+                Ok(false) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        let mut state = self.state.lock().unwrap();
+        state.flushing = false;
+        if sent > 0 {
+            self.rewrite(&state.pending)?;
+        }
+        result.map(|()| sent)
+    }
+    fn deliver(&self, entry: &Entry) -> Result<(), Error> {
+        if entry.notification {
+            self.client.notify(&entry.method, &entry.params)
+        } else {
+            self.client
+                .call_with_opts::<_, IgnoredAny>(&entry.method, &entry.params, &<_>::default())
+                .map(|_| ())
+        }
+    }
+    fn append(&self, entry: &Entry) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(entry).map_err(|e| Error::Encode(e.into()))?;
+        line.push(b'\n');
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        f.write_all(&line)?;
+        f.sync_data()?;
+        Ok(())
+    }
+    // the journal is replaced atomically
+    fn rewrite(&self, pending: &VecDeque<Entry>) -> Result<(), Error> {
+        let mut data = Vec::new();
+        for entry in pending {
+            serde_json::to_writer(&mut data, entry).map_err(|e| Error::Encode(e.into()))?;
+            data.push(b'\n');
+        }
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let mut f = fs::File::create(&tmp)?;
+        f.write_all(&data)?;
+        f.sync_data()?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Requests rejected with RPC errors are delivered, ones not sent because the
+/// server is unreachable are not, other errors stop the delivery
+fn delivered(result: Result<(), Error>) -> Result<bool, Error> {
+    match result {
+        Ok(()) | Err(Error::Rpc(_)) => Ok(true),
+        Err(e) if is_transient(&e) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+impl Entry {
+    fn new<P: Serialize>(
+        method: &str,
+        params: P,
+        key: Option<&str>,
+        notification: bool,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            method: method.to_owned(),
+            params: serde_json::to_value(params).map_err(|e| Error::Encode(e.into()))?,
+            key: key.map(ToOwned::to_owned),
+            notification,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Journal;
+    use crate::test_support::{serve, Reply, Response};
+    use crate::{http_client, ErrorKind};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    fn journal_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("bma-jrpc-{}-{}.journal", name, std::process::id()))
+    }
+
+    fn write_journal(name: &str, methods: &[&str]) -> PathBuf {
+        let path = journal_path(name);
+        let data: String = methods
+            .iter()
+            .map(|m| format!("{{\"method\":\"{}\",\"params\":null}}\n", m))
+            .collect();
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn read_journal(path: &PathBuf) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| {
+                let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+                entry["method"].as_str().unwrap().to_owned()
+            })
+            .collect()
+    }
+
+    /// Records methods received, replies as set for a method, with a result
+    /// otherwise. The "slow" method is delayed
+    fn serve_methods(replies: &'static [(&str, Reply)]) -> (String, Arc<Mutex<Vec<String>>>) {
+        let methods = Arc::new(Mutex::new(Vec::new()));
+        let received = methods.clone();
+        let url = serve(move |req| {
+            let body: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+            let method = body["method"].as_str().unwrap().to_owned();
+            if method == "slow" {
+                std::thread::sleep(Duration::from_millis(300));
+            }
+            received.lock().unwrap().push(method.clone());
+            match replies.iter().find(|(m, _)| *m == method) {
+                Some((_, Reply::Status(code))) => Response::status(*code),
+                Some((_, Reply::RpcError(code))) => Response::error(req, *code),
+                _ => Response::result(req, serde_json::Value::Null),
+            }
+        });
+        (url, methods)
+    }
+
+    #[test]
+    fn test_flush() {
+        let path = write_journal("flush", &["a", "b", "c"]);
+        let (url, methods) = serve_methods(&[("b", Reply::RpcError(-32000))]);
+        let journal = Journal::open(http_client(&url), &path).unwrap();
+        assert_eq!(journal.len(), 3);
+        // an RPC error means the request is delivered
+        assert_eq!(journal.flush().unwrap(), 3);
+        assert!(journal.is_empty());
+        assert!(read_journal(&path).is_empty());
+        assert_eq!(*methods.lock().unwrap(), ["a", "b", "c"]);
+        assert_eq!(journal.flush().unwrap(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_flush_unreachable() {
+        let path = write_journal("unreachable", &["a", "b", "c"]);
+        let (url, methods) = serve_methods(&[("b", Reply::Status(503))]);
+        let journal = Journal::open(http_client(&url), &path).unwrap();
+        assert_eq!(journal.flush().unwrap(), 1);
+        assert_eq!(journal.len(), 2);
+        assert_eq!(read_journal(&path), ["b", "c"]);
+        assert_eq!(*methods.lock().unwrap(), ["a", "b"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_flush_rejected() {
+        for code in [401, 403, 500] {
+            let path = write_journal(&format!("rejected-{}", code), &["a", "b", "c"]);
+            let replies: &'static [(&str, Reply)] = match code {
+                401 => &[("b", Reply::Status(401))],
+                403 => &[("b", Reply::Status(403))],
+                _ => &[("b", Reply::Status(500))],
+            };
+            let (url, methods) = serve_methods(replies);
+            let journal = Journal::open(http_client(&url), &path).unwrap();
+            // the request is kept, the delivery stops
+            let err = journal.flush().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Http);
+            assert_eq!(journal.len(), 2);
+            assert_eq!(read_journal(&path), ["b", "c"]);
+            assert_eq!(*methods.lock().unwrap(), ["a", "b"]);
+            // new requests are queued after the pending ones
+            assert!(journal.call("d", (), None).is_err());
+            assert_eq!(read_journal(&path), ["b", "c", "d"]);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_send() {
+        let path = write_journal("send", &["a"]);
+        let (url, methods) = serve_methods(&[("x", Reply::Status(401))]);
+        let journal = Journal::open(http_client(&url), &path).unwrap();
+        journal.call("b", (), None).unwrap();
+        journal.notify("c", (), None).unwrap();
+        assert_eq!(*methods.lock().unwrap(), ["a", "b", "c"]);
+        assert!(journal.is_empty());
+        // a rejected request is returned to the caller, not queued
+        let err = journal.call("x", (), None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Http);
+        assert!(journal.is_empty());
+        assert!(read_journal(&path).is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_send_unreachable() {
+        let path = journal_path("send-unreachable");
+        let _ = fs::remove_file(&path);
+        let journal = Journal::open(http_client("http://127.0.0.1:1"), &path).unwrap();
+        journal.call("a", (), Some("k")).unwrap();
+        journal.call("b", (), Some("k")).unwrap();
+        journal.call("c", (), None).unwrap();
+        assert_eq!(read_journal(&path), ["a", "c"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_send_while_flushing() {
+        let path = write_journal("flushing", &["slow"]);
+        let (url, methods) = serve_methods(&[]);
+        let journal = Journal::open(http_client(&url), &path).unwrap();
+        std::thread::scope(|scope| {
+            let flush = scope.spawn(|| journal.flush());
+            std::thread::sleep(Duration::from_millis(100));
+            // the request is queued without waiting for the delivery
+            let started = Instant::now();
+            assert_eq!(journal.len(), 1);
+            journal.call("fast", (), None).unwrap();
+            assert_eq!(journal.len(), 2);
+            assert!(started.elapsed() < Duration::from_millis(150));
+            assert_eq!(flush.join().unwrap().unwrap(), 2);
+        });
+        assert_eq!(*methods.lock().unwrap(), ["slow", "fast"]);
+        assert!(read_journal(&path).is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_tail() {
+        let path = journal_path("torn");
+        let complete = "{\"method\":\"a\",\"params\":[1]}\n{\"method\":\"b\",\"params\":null}\n";
+        fs::write(&path, format!("{}{{\"method\":\"c\",\"par", complete)).unwrap();
+        let journal = Journal::open(http_client("http://127.0.0.1:1"), &path).unwrap();
+        assert_eq!(journal.len(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), complete);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupted_line() {
+        let path = journal_path("corrupted");
+        fs::write(
+            &path,
+            "{\"method\":\"c\",\"par\n{\"method\":\"a\",\"params\":[1]}\n",
+        )
+        .unwrap();
+        assert!(Journal::open(http_client("http://127.0.0.1:1"), &path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
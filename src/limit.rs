@@ -0,0 +1,142 @@
+/// Limits the number of requests in flight, waiters are served in the order
+/// they came (the channel notifies receivers first-in, first-out)
+pub(crate) struct InFlightLimit {
+    tx: async_channel::Sender<()>,
+    rx: async_channel::Receiver<()>,
+}
+
+/// Returns the slot when dropped
+pub(crate) struct Permit<'a> {
+    tx: &'a async_channel::Sender<()>,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let _r = self.tx.try_send(());
+    }
+}
+
+impl InFlightLimit {
+    pub(crate) fn new(max: usize) -> Self {
+        let max = max.max(1);
+        let (tx, rx) = async_channel::bounded(max);
+        for _ in 0..max {
+            let _r = tx.try_send(());
+        }
+        Self { tx, rx }
+    }
+    // the channel is never closed as both ends are owned by the limit
+    pub(crate) fn acquire(&self) -> Permit<'_> {
+        let _r = self.rx.recv_blocking();
+        Permit { tx: &self.tx }
+    }
+    pub(crate) async fn acquire_async(&self) -> Permit<'_> {
+        let _r = self.rx.recv().await;
+        Permit { tx: &self.tx }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::InFlightLimit;
+    use crate::http_client;
+    use crate::test_support::{serve, Response};
+    use futures_lite::future;
+    use std::future::Future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::time::Duration;
+
+    // records the order in which waiters are woken
+    struct WakeOrder {
+        waiter: usize,
+        order: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl Wake for WakeOrder {
+        fn wake(self: Arc<Self>) {
+            self.order.lock().unwrap().push(self.waiter);
+        }
+    }
+
+    #[test]
+    fn test_fifo() {
+        const WAITERS: usize = 16;
+        let limit = InFlightLimit::new(1);
+        let permit = limit.acquire();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut waiters: Vec<_> = (0..WAITERS)
+            .map(|waiter| {
+                let waker = Waker::from(Arc::new(WakeOrder {
+                    waiter,
+                    order: order.clone(),
+                }));
+                let mut acquire = Box::pin(limit.acquire_async());
+                assert!(acquire
+                    .as_mut()
+                    .poll(&mut Context::from_waker(&waker))
+                    .is_pending());
+                (acquire, waker)
+            })
+            .collect();
+        drop(permit);
+        // woken waiters are polled in the order of wakeups, as executors do,
+        // every released slot must go to the next waiter
+        let mut served = Vec::new();
+        let mut polled = 0;
+        while served.len() < WAITERS {
+            let waiter = order.lock().unwrap().get(polled).copied();
+            let waiter = waiter.expect("no waiter has been woken");
+            polled += 1;
+            if served.contains(&waiter) {
+                continue;
+            }
+            let (ref mut acquire, ref waker) = waiters[waiter];
+            if let Poll::Ready(permit) = acquire.as_mut().poll(&mut Context::from_waker(waker)) {
+                served.push(waiter);
+                drop(permit);
+            }
+        }
+        assert_eq!(served, (0..WAITERS).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_max_in_flight() {
+        const TASKS: usize = 32;
+        const CALLS: usize = 4;
+        const MAX_IN_FLIGHT: usize = 4;
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let url = {
+            let max_in_flight = max_in_flight.clone();
+            serve(move |req| {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(current, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(10));
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Response::result(req, 1.into())
+            })
+        };
+        let client = http_client(&url).max_in_flight(MAX_IN_FLIGHT);
+        std::thread::scope(|scope| {
+            for _ in 0..TASKS {
+                scope.spawn(|| {
+                    future::block_on(async {
+                        for _ in 0..CALLS {
+                            let result: u8 = client.call_async("test", ()).await.unwrap();
+                            assert_eq!(result, 1);
+                        }
+                    });
+                });
+            }
+        });
+        let max_in_flight = max_in_flight.load(Ordering::SeqCst);
+        assert!(
+            max_in_flight <= MAX_IN_FLIGHT,
+            "{} in flight",
+            max_in_flight
+        );
+        assert!(max_in_flight > 1);
+    }
+}
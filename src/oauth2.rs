@@ -0,0 +1,297 @@
+use crate::limit::InFlightLimit;
+use crate::{AuthFuture, AuthProvider, ClientConfig, Clock, Error, ErrorBody, Extensions};
+use futures_lite::io::AsyncReadExt;
+use http::header::{HeaderValue, CONTENT_TYPE};
+use isahc::auth::{Authentication, Credentials};
+use isahc::config::Configurable;
+use isahc::RequestExt;
+use serde::Deserialize;
+use std::io::Read;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+// tokens are refreshed this long before they expire
+const REFRESH_MARGIN: Duration = Duration::from_secs(30);
+
+/// OAuth2 client credentials grant, tokens are cached until they are about to
+/// expire
+///
+/// The client authenticates to the token endpoint with HTTP Basic
+/// authentication. Async calls fetch tokens with async requests. A single
+/// token request is made at a time, concurrent calls wait for it. Token
+/// requests are sent with the network settings (proxy, interface etc.) of the
+/// first client which uses the provider, expiration is tracked with its clock.
+pub struct ClientCredentials {
+    token_url: String,
+    credentials: Credentials,
+    scopes: Vec<String>,
+    timeout: Option<Duration>,
+    client: OnceLock<(ClientConfig, Arc<dyn Clock>)>,
+    token: Mutex<Option<CachedToken>>,
+    refresh: InFlightLimit,
+}
+
+struct CachedToken {
+    value: HeaderValue,
+    expires: Option<Instant>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    token_type: Option<String>,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+impl ClientCredentials {
+    pub fn new(token_url: &str, client_id: &str, client_secret: &str) -> Self {
+        Self {
+            token_url: token_url.to_owned(),
+            credentials: Credentials::new(client_id, client_secret),
+            scopes: Vec::new(),
+            timeout: None,
+            client: OnceLock::new(),
+            token: <_>::default(),
+            refresh: InFlightLimit::new(1),
+        }
+    }
+    #[inline]
+    pub fn scopes(mut self, scopes: &[&str]) -> Self {
+        self.scopes = scopes.iter().map(ToString::to_string).collect();
+        self
+    }
+    /// Token request timeout, the timeout of the client by default (10
+    /// seconds if the provider is used on its own)
+    #[inline]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    fn now(&self) -> Instant {
+        self.client
+            .get()
+            .map_or_else(Instant::now, |(_, clock)| clock.now())
+    }
+    fn token_request(&self) -> Result<isahc::Request<String>, Error> {
+        let mut form = form_urlencoded::Serializer::new(String::new());
+        form.append_pair("grant_type", "client_credentials");
+        if !self.scopes.is_empty() {
+            form.append_pair("scope", &self.scopes.join(" "));
+        }
+        let builder = isahc::Request::post(&self.token_url);
+        let builder = match self.client.get() {
+            Some((config, _)) => {
+                config.configure_connection(builder, self.timeout.unwrap_or(config.timeout()))
+            }
+            None => builder.timeout(self.timeout.unwrap_or(DEFAULT_TIMEOUT)),
+        };
+        Ok(builder
+            .authentication(Authentication::basic())
+            .credentials(self.credentials.clone())
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form.finish())?)
+    }
+    fn cached(&self) -> Option<HeaderValue> {
+        self.token
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|cached| cached.expires.is_none_or(|expires| self.now() < expires))
+            .map(|cached| cached.value.clone())
+    }
+    fn fetch(&self) -> Result<HeaderValue, Error> {
+        let requested = self.now();
+        let mut resp = self.token_request()?.send()?;
+        if !resp.status().is_success() {
+            return Err(Error::Http(resp.status(), ErrorBody::read(resp.body_mut())));
+        }
+        let mut buf = Vec::new();
+        resp.body_mut()
+            .read_to_end(&mut buf)
+            .map_err(isahc::Error::from)?;
+        self.store(&buf, requested)
+    }
+    async fn fetch_async(&self) -> Result<HeaderValue, Error> {
+        let requested = self.now();
+        let mut resp = self.token_request()?.send_async().await?;
+        if !resp.status().is_success() {
+            return Err(Error::Http(
+                resp.status(),
+                ErrorBody::read_async(resp.body_mut()).await,
+            ));
+        }
+        let mut buf = Vec::new();
+        resp.body_mut()
+            .read_to_end(&mut buf)
+            .await
+            .map_err(isahc::Error::from)?;
+        self.store(&buf, requested)
+    }
+    fn store(&self, buf: &[u8], requested: Instant) -> Result<HeaderValue, Error> {
+        let token: TokenResponse = serde_json::from_slice(buf)?;
+        let token_type = match token.token_type {
+            // the type is case-insensitive, servers may return "bearer"
+            Some(ref t) if !t.eq_ignore_ascii_case("bearer") => t.as_str(),
+            _ => "Bearer",
+        };
+        let value = HeaderValue::try_from(format!("{} {}", token_type, token.access_token))
+            .map_err(|_| Error::Protocol("invalid access token"))?;
+        *self.token.lock().unwrap() = Some(CachedToken {
+            value: value.clone(),
+            expires: token
+                .expires_in
+                .map(|secs| requested + Duration::from_secs(secs).saturating_sub(REFRESH_MARGIN)),
+        });
+        Ok(value)
+    }
+}
+
+impl AuthProvider for ClientCredentials {
+    fn authorization(&self) -> Result<HeaderValue, Error> {
+        if let Some(value) = self.cached() {
+            return Ok(value);
+        }
+        let _permit = self.refresh.acquire();
+        // the token may have been fetched while waiting
+        if let Some(value) = self.cached() {
+            return Ok(value);
+        }
+        self.fetch()
+    }
+    fn authorization_async<'a>(&'a self, _extensions: &'a Extensions) -> AuthFuture<'a> {
+        Box::pin(async move {
+            if let Some(value) = self.cached() {
+                return Ok(value);
+            }
+            let _permit = self.refresh.acquire_async().await;
+            if let Some(value) = self.cached() {
+                return Ok(value);
+            }
+            self.fetch_async().await
+        })
+    }
+    fn invalidate(&self) {
+        self.token.lock().unwrap().take();
+    }
+    fn bind(&self, config: &ClientConfig, clock: &Arc<dyn Clock>) {
+        self.client.get_or_init(|| (config.clone(), clock.clone()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ClientCredentials;
+    use crate::test_support::{serve, Response};
+    use crate::{http_client, Error, HttpClient, Json, Rpc, TestClock};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    struct Server {
+        url: String,
+        tokens: Arc<AtomicUsize>,
+        revoked: Arc<Mutex<Option<String>>>,
+    }
+
+    // issues tokens t0, t1... valid for a minute, RPC calls return the
+    // Authorization header
+    fn serve_tokens(delay: Duration) -> Server {
+        let tokens = Arc::new(AtomicUsize::new(0));
+        let revoked = Arc::new(Mutex::new(None::<String>));
+        let (counter, rejected) = (tokens.clone(), revoked.clone());
+        let url = serve(move |req| {
+            if req.path.ends_with("/token") {
+                assert!(req.header("authorization").unwrap().starts_with("Basic "));
+                std::thread::sleep(delay);
+                let n = counter.fetch_add(1, Ordering::SeqCst);
+                return Response::json(&serde_json::json!({
+                    "access_token": format!("t{}", n),
+                    "token_type": "bearer",
+                    "expires_in": 60
+                }));
+            }
+            let authorization = req.header("authorization").unwrap_or_default();
+            if rejected.lock().unwrap().as_deref() == Some(authorization) {
+                return Response::status(401);
+            }
+            Response::result(req, authorization.into())
+        });
+        Server {
+            url,
+            tokens,
+            revoked,
+        }
+    }
+
+    fn client(server: &Server, clock: Arc<TestClock>) -> HttpClient<Json> {
+        http_client(&server.url)
+            .clock(clock)
+            .auth_provider(ClientCredentials::new(
+                &format!("{}/token", server.url),
+                "client",
+                "secret",
+            ))
+    }
+
+    fn call(client: &HttpClient<Json>) -> Result<String, Error> {
+        client.call("test", ())
+    }
+
+    #[test]
+    fn test_expiry() {
+        let server = serve_tokens(Duration::ZERO);
+        let clock = Arc::new(TestClock::new());
+        let client = client(&server, clock.clone());
+        assert_eq!(call(&client).unwrap(), "Bearer t0");
+        assert_eq!(call(&client).unwrap(), "Bearer t0");
+        assert_eq!(server.tokens.load(Ordering::SeqCst), 1);
+        // refreshed 30 seconds before the token expires
+        clock.advance(Duration::from_secs(29));
+        assert_eq!(call(&client).unwrap(), "Bearer t0");
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(call(&client).unwrap(), "Bearer t1");
+        assert_eq!(
+            futures_lite::future::block_on(client.call_async::<_, String>("test", ())).unwrap(),
+            "Bearer t1"
+        );
+        assert_eq!(server.tokens.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let server = serve_tokens(Duration::ZERO);
+        let client = client(&server, Arc::new(TestClock::new()));
+        assert_eq!(call(&client).unwrap(), "Bearer t0");
+        server
+            .revoked
+            .lock()
+            .unwrap()
+            .replace("Bearer t0".to_owned());
+        assert!(matches!(call(&client), Err(Error::Http(status, _)) if status == 401));
+        assert_eq!(call(&client).unwrap(), "Bearer t1");
+    }
+
+    #[test]
+    fn test_client_settings() {
+        let server = serve_tokens(Duration::from_millis(300));
+        // the token host is reachable via the proxy only
+        let client = http_client("http://rpc.invalid/rpc")
+            .proxy(server.url.parse().unwrap())
+            .auth_provider(ClientCredentials::new(
+                "http://auth.invalid/token",
+                "client",
+                "secret",
+            ));
+        assert_eq!(call(&client).unwrap(), "Bearer t0");
+        let client = client.timeout(Duration::from_millis(100));
+        let client = client.auth_provider(ClientCredentials::new(
+            "http://auth.invalid/token",
+            "client",
+            "secret",
+        ));
+        assert!(call(&client).unwrap_err().is_timeout());
+    }
+}
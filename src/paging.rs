@@ -0,0 +1,260 @@
+use crate::{Encoder, Error, HttpClient};
+use futures_lite::Stream;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::collections::VecDeque;
+
+/// Pagination convention of a list method, see [`HttpClient::call_paged`]
+#[derive(Clone, Debug)]
+pub struct PageSpec {
+    kind: PageKind,
+    items: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+enum PageKind {
+    Offset {
+        offset: String,
+        limit: String,
+        page_size: u64,
+    },
+    Cursor {
+        cursor: String,
+        next: String,
+    },
+}
+
+impl PageSpec {
+    /// Offset/limit params, the offset grows by the number of items received,
+    /// a page shorter than `page_size` is the last one. The result is the
+    /// array of items
+    pub fn offset(offset_field: &str, limit_field: &str, page_size: u64) -> Self {
+        Self {
+            kind: PageKind::Offset {
+                offset: offset_field.to_owned(),
+                limit: limit_field.to_owned(),
+                page_size,
+            },
+            items: None,
+        }
+    }
+    /// Cursor param, taken from the `next_field` member of the result, paging
+    /// stops when it is null or missing. The items are in the `items` member
+    pub fn cursor(cursor_field: &str, next_field: &str) -> Self {
+        Self {
+            kind: PageKind::Cursor {
+                cursor: cursor_field.to_owned(),
+                next: next_field.to_owned(),
+            },
+            items: Some("items".to_owned()),
+        }
+    }
+    /// The result member which holds the items
+    #[inline]
+    pub fn items(mut self, field: &str) -> Self {
+        self.items = Some(field.to_owned());
+        self
+    }
+}
+
+struct Pager {
+    method: String,
+    params: Map<String, Value>,
+    spec: PageSpec,
+    offset: u64,
+    items: VecDeque<Value>,
+    done: bool,
+}
+
+impl Pager {
+    async fn fetch<C: Encoder>(&mut self, client: &HttpClient<C>) -> Result<(), Error> {
+        if let PageKind::Offset {
+            ref offset,
+            ref limit,
+            page_size,
+        } = self.spec.kind
+        {
+            self.params.insert(offset.clone(), self.offset.into());
+            self.params.insert(limit.clone(), page_size.into());
+        }
+        let mut result: Value = client.call_async(&self.method, &self.params).await?;
+        let page = match self.spec.items {
+            Some(ref field) => result.get_mut(field).map(Value::take),
+            None => Some(result.take()),
+        };
+        let Some(Value::Array(page)) = page else {
+            return Err(Error::Protocol("page is not an array"));
+        };
+        let received = page.len() as u64;
+        match self.spec.kind {
+            PageKind::Offset { page_size, .. } => {
+                self.offset += received;
+                self.done = received == 0 || received < page_size;
+            }
+            PageKind::Cursor {
+                ref cursor,
+                ref next,
+            } => match result.get_mut(next).map(Value::take) {
+                // an empty page with a cursor would loop forever
+                Some(value) if !value.is_null() && value != "" && received > 0 => {
+                    self.params.insert(cursor.clone(), value);
+                }
+                _ => self.done = true,
+            },
+        }
+        self.items.extend(page);
+        Ok(())
+    }
+}
+
+pub(crate) fn paged<'a, C, T>(
+    client: &'a HttpClient<C>,
+    method: &str,
+    params: Result<Value, Error>,
+    spec: PageSpec,
+) -> impl Stream<Item = Result<T, Error>> + Send + 'a
+where
+    C: Encoder,
+    T: DeserializeOwned + Send + 'a,
+{
+    let (params, error) = match params {
+        Ok(Value::Object(params)) => (params, None),
+        Ok(Value::Null) => (Map::new(), None),
+        Ok(_) => (
+            Map::new(),
+            Some(Error::Encode("paged calls require named params".into())),
+        ),
+        Err(e) => (Map::new(), Some(e)),
+    };
+    let pager = Pager {
+        method: method.to_owned(),
+        params,
+        spec,
+        offset: 0,
+        items: VecDeque::new(),
+        done: false,
+    };
+    futures_lite::stream::unfold((pager, error), move |(mut pager, mut error)| async move {
+        loop {
+            if let Some(e) = error.take() {
+                pager.done = true;
+                return Some((Err(e), (pager, None)));
+            }
+            if let Some(item) = pager.items.pop_front() {
+                return Some((
+                    serde_json::from_value(item).map_err(Into::into),
+                    (pager, None),
+                ));
+            }
+            if pager.done {
+                return None;
+            }
+            if let Err(e) = pager.fetch(client).await {
+                error = Some(e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::PageSpec;
+    use crate::test_support::{serve, Response};
+    use crate::{http_client, Error, ErrorKind};
+    use futures_lite::StreamExt;
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // serves items 0..total, returns the URL and the call counter
+    fn serve_items(total: u64) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let url = serve(move |req| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let call: Value = serde_json::from_slice(&req.body).unwrap();
+            let params = &call["params"];
+            match call["method"].as_str().unwrap() {
+                "list" => {
+                    let offset = params["offset"].as_u64().unwrap();
+                    let limit = params["limit"].as_u64().unwrap();
+                    let items: Vec<u64> = (offset..total.min(offset + limit)).collect();
+                    Response::result(req, json!(items))
+                }
+                "scan" => {
+                    // cursors are the next item, pages are 3 items
+                    let from = params.get("cursor").map_or(0, |c| c.as_u64().unwrap());
+                    let to = total.min(from + 3);
+                    let next = if to < total { json!(to) } else { Value::Null };
+                    Response::result(
+                        req,
+                        json!({ "entries": (from..to).collect::<Vec<_>>(), "next": next }),
+                    )
+                }
+                _ => Response::result(req, json!({ "items": 1 })),
+            }
+        });
+        (url, calls)
+    }
+
+    fn collect<S: futures_lite::Stream<Item = Result<u64, Error>>>(
+        stream: S,
+    ) -> Vec<Result<u64, Error>> {
+        futures_lite::future::block_on(stream.collect())
+    }
+
+    #[test]
+    fn test_offset() {
+        let (url, calls) = serve_items(25);
+        let client = http_client(&url);
+        let items: Vec<u64> =
+            collect(client.call_paged("list", (), PageSpec::offset("offset", "limit", 10)))
+                .into_iter()
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(items, (0..25).collect::<Vec<_>>());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        // a full last page is followed by an empty one
+        let (url, calls) = serve_items(20);
+        let client = http_client(&url);
+        let items = collect(client.call_paged(
+            "list",
+            json!({ "filter": "all" }),
+            PageSpec::offset("offset", "limit", 10),
+        ));
+        assert_eq!(items.len(), 20);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_cursor() {
+        let (url, calls) = serve_items(7);
+        let client = http_client(&url);
+        let items: Vec<u64> = collect(client.call_paged(
+            "scan",
+            (),
+            PageSpec::cursor("cursor", "next").items("entries"),
+        ))
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .unwrap();
+        assert_eq!(items, (0..7).collect::<Vec<_>>());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_errors() {
+        let (url, calls) = serve_items(5);
+        let client = http_client(&url);
+        let items = collect(client.call_paged("other", (), PageSpec::cursor("cursor", "next")));
+        assert!(matches!(
+            items[..],
+            [Err(Error::Protocol("page is not an array"))]
+        ));
+        let items =
+            collect(client.call_paged("list", [1, 2], PageSpec::offset("offset", "limit", 10)));
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].as_ref().unwrap_err().kind(), ErrorKind::Encode);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
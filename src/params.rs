@@ -0,0 +1,328 @@
+use serde::ser::{Impossible, SerializeMap, SerializeTuple, Serializer};
+use serde::Serialize;
+
+/// Params sent as an array (positional), for servers which do not accept
+/// named params. Usually created with [`params!`](crate::params)
+#[derive(Debug, Clone, Copy)]
+pub struct Positional<T: PositionalParams>(pub T);
+
+impl<T: PositionalParams> Serialize for Positional<T> {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize_positional(serializer)
+    }
+}
+
+/// Values which can be sent as positional params: unit (no values), tuples,
+/// arrays and vectors
+pub trait PositionalParams {
+    fn serialize_positional<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>;
+}
+
+impl PositionalParams for () {
+    #[inline]
+    fn serialize_positional<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_tuple(0)?.end()
+    }
+}
+
+impl<T: Serialize, const N: usize> PositionalParams for [T; N] {
+    #[inline]
+    fn serialize_positional<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.as_slice().serialize(serializer)
+    }
+}
+
+impl<T: Serialize> PositionalParams for Vec<T> {
+    #[inline]
+    fn serialize_positional<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.serialize(serializer)
+    }
+}
+
+macro_rules! impl_positional_tuple {
+    ($len: expr, $($name: ident: $idx: tt),+) => {
+        impl<$($name: Serialize),+> PositionalParams for ($($name,)+) {
+            fn serialize_positional<S: Serializer>(
+                &self,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                let mut tuple = serializer.serialize_tuple($len)?;
+                $(tuple.serialize_element(&self.$idx)?;)+
+                tuple.end()
+            }
+        }
+    };
+}
+
+impl_positional_tuple!(1, A: 0);
+impl_positional_tuple!(2, A: 0, B: 1);
+impl_positional_tuple!(3, A: 0, B: 1, C: 2);
+impl_positional_tuple!(4, A: 0, B: 1, C: 2, D: 3);
+impl_positional_tuple!(5, A: 0, B: 1, C: 2, D: 3, E: 4);
+impl_positional_tuple!(6, A: 0, B: 1, C: 2, D: 3, E: 4, F: 5);
+impl_positional_tuple!(7, A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6);
+impl_positional_tuple!(8, A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7);
+impl_positional_tuple!(9, A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8);
+impl_positional_tuple!(10, A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8, J: 9);
+impl_positional_tuple!(11, A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8, J: 9, K: 10);
+impl_positional_tuple!(
+    12, A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8, J: 9, K: 10, L: 11
+);
+
+/// Creates positional params: `params![]`, `params![1, "x", true]`
+#[macro_export]
+macro_rules! params {
+    () => {
+        $crate::Positional(())
+    };
+    ($($value: expr),+ $(,)?) => {
+        $crate::Positional(($($value,)+))
+    };
+}
+
+/// How unit params (`()`) are sent
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum UnitParams {
+    /// `"params": null`
+    #[default]
+    Null,
+    /// `"params": []`
+    EmptyArray,
+    /// `"params": {}`
+    EmptyObject,
+    /// no params member
+    Omit,
+}
+
+pub(crate) enum ParamsRepr<P> {
+    Value(P),
+    EmptyArray,
+    EmptyObject,
+}
+
+impl<P: Serialize> Serialize for ParamsRepr<P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ParamsRepr::Value(params) => params.serialize(serializer),
+            ParamsRepr::EmptyArray => serializer.serialize_tuple(0)?.end(),
+            ParamsRepr::EmptyObject => serializer.serialize_map(Some(0))?.end(),
+        }
+    }
+}
+
+impl UnitParams {
+    pub(crate) fn apply<P: Serialize>(self, params: Option<P>) -> Option<ParamsRepr<P>> {
+        let params = params?;
+        match self {
+            UnitParams::Null => Some(ParamsRepr::Value(params)),
+            _ if !is_unit(&params) => Some(ParamsRepr::Value(params)),
+            UnitParams::EmptyArray => Some(ParamsRepr::EmptyArray),
+            UnitParams::EmptyObject => Some(ParamsRepr::EmptyObject),
+            UnitParams::Omit => None,
+        }
+    }
+}
+
+fn is_unit<P: Serialize>(params: &P) -> bool {
+    params.serialize(UnitProbe).unwrap_or(false)
+}
+
+// a serializer which only checks if a value is unit
+struct UnitProbe;
+
+#[derive(Debug)]
+struct NotUnit;
+
+impl std::fmt::Display for NotUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "not unit")
+    }
+}
+
+impl std::error::Error for NotUnit {}
+
+impl serde::ser::Error for NotUnit {
+    fn custom<T: std::fmt::Display>(_msg: T) -> Self {
+        NotUnit
+    }
+}
+
+macro_rules! probe_not_unit {
+    ($($method: ident($($arg: ty),*)),+) => {
+        $(
+            #[inline]
+            fn $method(self, $(_: $arg),*) -> Result<bool, NotUnit> {
+                Ok(false)
+            }
+        )+
+    };
+}
+
+impl Serializer for UnitProbe {
+    type Ok = bool;
+    type Error = NotUnit;
+    type SerializeSeq = Impossible<bool, NotUnit>;
+    type SerializeTuple = Impossible<bool, NotUnit>;
+    type SerializeTupleStruct = Impossible<bool, NotUnit>;
+    type SerializeTupleVariant = Impossible<bool, NotUnit>;
+    type SerializeMap = Impossible<bool, NotUnit>;
+    type SerializeStruct = Impossible<bool, NotUnit>;
+    type SerializeStructVariant = Impossible<bool, NotUnit>;
+
+    #[inline]
+    fn serialize_unit(self) -> Result<bool, NotUnit> {
+        Ok(true)
+    }
+    probe_not_unit!(
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_none(),
+        serialize_unit_struct(&'static str),
+        serialize_unit_variant(&'static str, u32, &'static str)
+    );
+    #[inline]
+    fn serialize_some<T: ?Sized + Serialize>(self, _value: &T) -> Result<bool, NotUnit> {
+        Ok(false)
+    }
+    #[inline]
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _value: &T,
+    ) -> Result<bool, NotUnit> {
+        Ok(false)
+    }
+    #[inline]
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<bool, NotUnit> {
+        Ok(false)
+    }
+    #[inline]
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, NotUnit> {
+        Err(NotUnit)
+    }
+    #[inline]
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, NotUnit> {
+        Err(NotUnit)
+    }
+    #[inline]
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, NotUnit> {
+        Err(NotUnit)
+    }
+    #[inline]
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, NotUnit> {
+        Err(NotUnit)
+    }
+    #[inline]
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, NotUnit> {
+        Err(NotUnit)
+    }
+    #[inline]
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, NotUnit> {
+        Err(NotUnit)
+    }
+    #[inline]
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, NotUnit> {
+        Err(NotUnit)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_unit, Positional, UnitParams};
+    use crate::test_support::{serve, Response};
+    use crate::{http_client, Rpc};
+    use serde_json::{json, Value};
+
+    fn to_json<P: serde::Serialize>(params: P) -> Value {
+        serde_json::to_value(params).unwrap()
+    }
+
+    #[test]
+    fn test_params_macro() {
+        assert_eq!(to_json(params![]), json!([]));
+        // a single value is still an array
+        assert_eq!(to_json(params![1]), json!([1]));
+        assert_eq!(to_json(params![1, "x", true,]), json!([1, "x", true]));
+        assert_eq!(
+            to_json(params![Some(1), None::<u8>, [1, 2]]),
+            json!([1, null, [1, 2]])
+        );
+        assert_eq!(to_json(Positional([1, 2, 3])), json!([1, 2, 3]));
+        assert_eq!(to_json(Positional(vec!["a"])), json!(["a"]));
+        assert_eq!(
+            to_json(params![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]),
+            json!([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12])
+        );
+    }
+
+    #[test]
+    fn test_unit_params() {
+        assert!(is_unit(&()));
+        assert!(!is_unit(&params![]));
+        assert!(!is_unit(&None::<()>));
+        assert!(!is_unit(&json!({})));
+        let apply = |unit: UnitParams, params: Option<Value>| unit.apply(params).map(to_json);
+        let unit = Some(Value::Null);
+        assert_eq!(apply(UnitParams::Null, unit.clone()), Some(Value::Null));
+        assert_eq!(apply(UnitParams::EmptyArray, unit.clone()), Some(json!([])));
+        assert_eq!(
+            apply(UnitParams::EmptyObject, unit.clone()),
+            Some(json!({}))
+        );
+        assert_eq!(apply(UnitParams::Omit, unit), None);
+        assert_eq!(apply(UnitParams::Omit, Some(json!([1]))), Some(json!([1])));
+        assert_eq!(apply(UnitParams::EmptyArray, None), None);
+    }
+
+    #[test]
+    fn test_call() {
+        let url = serve(|req| {
+            let call: Value = serde_json::from_slice(&req.body).unwrap();
+            Response::result(req, call["params"].clone())
+        });
+        let client = http_client(&url);
+        let params: Value = client.call("echo", params!["a", 2]).unwrap();
+        assert_eq!(params, json!(["a", 2]));
+        let params: Value = client.call("echo", params![]).unwrap();
+        assert_eq!(params, json!([]));
+    }
+}
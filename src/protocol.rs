@@ -0,0 +1,888 @@
+//! Sans-IO JSON RPC core: request encoding, id management and response
+//! matching, shared by all transports
+use crate::params::UnitParams;
+use crate::{Encoder, Error};
+use serde::de::{value::UnitDeserializer, IgnoredAny};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic;
+
+pub(crate) const JSONRPC_VER: &str = "2.0";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
+#[serde(untagged)]
+pub enum Id {
+    Number(u64),
+    String(String),
+}
+
+impl From<u64> for Id {
+    #[inline]
+    fn from(id: u64) -> Self {
+        Self::Number(id)
+    }
+}
+
+impl Id {
+    #[inline]
+    pub(crate) fn matches(&self, id: u64, compat: &Compat) -> bool {
+        self.to_u64(compat) == Some(id)
+    }
+    /// The ID as a JSON value
+    pub(crate) fn to_json(&self) -> String {
+        match self {
+            Id::Number(n) => n.to_string(),
+            Id::String(s) => serde_json::Value::from(s.as_str()).to_string(),
+        }
+    }
+    #[inline]
+    fn to_u64(&self, compat: &Compat) -> Option<u64> {
+        match self {
+            Id::Number(n) => Some(*n),
+            Id::String(s) if compat.coerce_ids || compat.string_ids => s.parse().ok(),
+            Id::String(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Id::Number(n) => write!(f, "{}", n),
+            Id::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// JSON RPC protocol version
+#[derive(Default, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProtocolVersion {
+    /// JSON RPC 1.0: no version member, notifications have null IDs, responses
+    /// carry both result and error members
+    V1,
+    #[default]
+    V2,
+}
+
+/// Request ID allocation strategy
+///
+/// IDs must be unique among the requests in flight of a client, they are not
+/// required to be sequential.
+pub trait IdGenerator: Send + Sync {
+    fn next_id(&self) -> u64;
+}
+
+/// Request ID generator, a shared counter
+///
+/// Every call returns a unique ID. No ordering with other memory operations is
+/// guaranteed, IDs are allocated in the counter order only.
+#[derive(Debug, Default)]
+pub struct IdSequence {
+    next: atomic::AtomicU64,
+}
+
+impl IdSequence {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    #[inline]
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&self) -> u64 {
+        // the read-modify-write is atomic with any ordering, so IDs are unique
+        self.next.fetch_add(1, atomic::Ordering::Relaxed)
+    }
+}
+
+impl IdGenerator for IdSequence {
+    #[inline]
+    fn next_id(&self) -> u64 {
+        self.next()
+    }
+}
+
+/// Workarounds for servers which do not follow the JSON RPC specification
+#[derive(Default, Clone, Debug)]
+pub struct Compat {
+    coerce_ids: bool,
+    string_ids: bool,
+    strict_members: bool,
+    pub(crate) unit_params: UnitParams,
+    pub(crate) version: ProtocolVersion,
+}
+
+impl Compat {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Accepts response IDs echoed back as strings ("42" for 42)
+    #[inline]
+    pub fn coerce_ids(mut self) -> Self {
+        self.coerce_ids = true;
+        self
+    }
+    /// Sends request IDs as strings ("42" for 42), responses are accepted with
+    /// either form
+    #[inline]
+    pub fn string_ids(mut self) -> Self {
+        self.string_ids = true;
+        self
+    }
+    /// Rejects responses which carry both result and error members, by default
+    /// the error is taken
+    #[inline]
+    pub fn strict_members(mut self) -> Self {
+        self.strict_members = true;
+        self
+    }
+    /// Sets the protocol version, 2.0 by default
+    #[inline]
+    pub fn protocol_version(mut self, version: ProtocolVersion) -> Self {
+        self.version = version;
+        self
+    }
+    /// The ID member of a request
+    #[inline]
+    pub(crate) fn request_id(&self, id: u64) -> Id {
+        if self.string_ids {
+            Id::String(id.to_string())
+        } else {
+            Id::Number(id)
+        }
+    }
+    /// Checks the version member of a response
+    pub(crate) fn check_version(&self, jsonrpc: Option<&str>) -> Result<(), Error> {
+        match self.version {
+            ProtocolVersion::V1 => Ok(()),
+            ProtocolVersion::V2 if jsonrpc == Some(JSONRPC_VER) => Ok(()),
+            ProtocolVersion::V2 => Err(Error::Protocol("invalid JSON RPC version")),
+        }
+    }
+    /// Sets how unit params (`()`) are sent, null by default
+    #[inline]
+    pub fn unit_params(mut self, unit_params: UnitParams) -> Self {
+        self.unit_params = unit_params;
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct Request<'a, P> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    jsonrpc: Option<&'static str>,
+    // 1.0 notifications have null IDs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<Option<Id>>,
+    method: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    params: Option<P>,
+}
+
+// positional encoders put members by their order, so all of them are sent,
+// omitted ones as nulls
+#[derive(Serialize)]
+struct PositionalRequest<'a, P> {
+    jsonrpc: Option<&'static str>,
+    id: Option<Id>,
+    method: &'a str,
+    params: Option<P>,
+}
+
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "R: Deserialize<'de>, 'de: 'a"))]
+struct Response<'a, R> {
+    #[serde(default, borrow)]
+    jsonrpc: Option<&'a str>,
+    id: Id,
+    #[serde(default, deserialize_with = "member")]
+    result: Member<R>,
+    error: Option<RpcError>,
+}
+
+// entries of batch responses may have null IDs if the server could not
+// process the request
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "R: Deserialize<'de>, 'de: 'a"))]
+struct BatchEntry<'a, R> {
+    #[serde(default, borrow)]
+    jsonrpc: Option<&'a str>,
+    id: Option<Id>,
+    #[serde(default, deserialize_with = "member")]
+    result: Member<R>,
+    error: Option<RpcError>,
+}
+
+// a null member is told apart from an absent one
+enum Member<R> {
+    Absent,
+    Null,
+    Value(R),
+}
+
+impl<R> Default for Member<R> {
+    #[inline]
+    fn default() -> Self {
+        Self::Absent
+    }
+}
+
+fn member<'de, D, R>(deserializer: D) -> Result<Member<R>, D::Error>
+where
+    D: Deserializer<'de>,
+    R: Deserialize<'de>,
+{
+    Option::<R>::deserialize(deserializer).map(|v| v.map_or(Member::Null, Member::Value))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct RpcError {
+    code: i16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+impl RpcError {
+    #[inline]
+    pub fn new(code: i16, message: Option<String>) -> Self {
+        Self { code, message }
+    }
+    #[inline]
+    pub fn code(&self) -> i16 {
+        self.code
+    }
+    #[inline]
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
+
+/// Encodes a request into the buffer, requests with no ID are notifications,
+/// requests with no params have the params member omitted
+pub fn encode_request<C, P>(
+    encoder: &C,
+    method: &str,
+    params: Option<P>,
+    id: Option<u64>,
+    compat: &Compat,
+    buf: &mut Vec<u8>,
+) -> Result<(), Error>
+where
+    C: Encoder,
+    P: Serialize,
+{
+    encode_envelope(
+        encoder,
+        method,
+        compat.unit_params.apply(params),
+        id,
+        compat,
+        buf,
+    )
+}
+
+/// Encodes a request with params encoded before (see
+/// [`Encoder::replace_trailing_null`]), unit params are not converted
+pub(crate) fn encode_request_raw<C: Encoder>(
+    encoder: &C,
+    method: &str,
+    params: Option<&[u8]>,
+    id: Option<u64>,
+    compat: &Compat,
+    buf: &mut Vec<u8>,
+) -> Result<(), Error> {
+    // params are the last member, the null placeholder is replaced
+    encode_envelope(encoder, method, params.map(|_| ()), id, compat, buf)?;
+    if let Some(params) = params {
+        encoder.replace_trailing_null(buf, params)?;
+    }
+    Ok(())
+}
+
+fn encode_envelope<C, P>(
+    encoder: &C,
+    method: &str,
+    params: Option<P>,
+    id: Option<u64>,
+    compat: &Compat,
+    buf: &mut Vec<u8>,
+) -> Result<(), Error>
+where
+    C: Encoder,
+    P: Serialize,
+{
+    let id = id.map(|id| compat.request_id(id));
+    let jsonrpc = (compat.version == ProtocolVersion::V2).then_some(JSONRPC_VER);
+    if encoder.is_positional() {
+        let req = PositionalRequest {
+            jsonrpc,
+            id,
+            method,
+            params,
+        };
+        return encoder.encode_into(&req, buf);
+    }
+    let req = Request {
+        jsonrpc,
+        id: match compat.version {
+            ProtocolVersion::V1 => Some(id),
+            ProtocolVersion::V2 => id.map(Some),
+        },
+        method,
+        params,
+    };
+    encoder.encode_into(&req, buf)
+}
+
+/// Decodes a response to the request with the given ID
+pub fn parse_response<'a, C: Encoder, R: Deserialize<'a>>(
+    encoder: &C,
+    buf: &'a [u8],
+    id: u64,
+    compat: &Compat,
+) -> Result<R, Error> {
+    if buf.is_empty() {
+        // 204 or an empty body is valid for methods which return nothing
+        return unit_result().map_err(|_| Error::Protocol("empty response"));
+    }
+    let resp: Response<R> = encoder.decode(buf)?;
+    compat.check_version(resp.jsonrpc)?;
+    if !resp.id.matches(id, compat) {
+        return Err(Error::Protocol("invalid response ID"));
+    }
+    select_member(resp.result, resp.error, compat)
+}
+
+/// Decodes a response which has been already accepted by a client, e.g. a
+/// recorded one. The ID and the protocol version are not checked
+pub fn parse_accepted_response<'a, C: Encoder, R: Deserialize<'a>>(
+    encoder: &C,
+    buf: &'a [u8],
+) -> Result<R, Error> {
+    if buf.is_empty() {
+        return unit_result().map_err(|_| Error::Protocol("empty response"));
+    }
+    let resp: Response<R> = encoder.decode(buf)?;
+    let compat = Compat {
+        version: ProtocolVersion::V1,
+        ..Compat::default()
+    };
+    select_member(resp.result, resp.error, &compat)
+}
+
+fn unit_result<'a, R: Deserialize<'a>>() -> Result<R, serde::de::value::Error> {
+    R::deserialize(UnitDeserializer::<serde::de::value::Error>::new())
+}
+
+// the error wins if both members are set, 1.0 responses carry both members,
+// the one not used is null
+fn select_member<'a, R: Deserialize<'a>>(
+    result: Member<R>,
+    error: Option<RpcError>,
+    compat: &Compat,
+) -> Result<R, Error> {
+    if let Some(err) = error {
+        let ambiguous = match result {
+            Member::Absent => false,
+            Member::Null => compat.version == ProtocolVersion::V2,
+            Member::Value(_) => true,
+        };
+        if ambiguous && compat.strict_members {
+            return Err(Error::Protocol("both result and error fields"));
+        }
+        return Err(Error::Rpc(err));
+    }
+    match result {
+        Member::Value(result) => Ok(result),
+        Member::Null => unit_result().map_err(|_| Error::Protocol("null result")),
+        Member::Absent => match compat.version {
+            ProtocolVersion::V1 => unit_result().map_err(|_| Error::Protocol("null result")),
+            ProtocolVersion::V2 => Err(Error::Protocol("no result/error fields")),
+        },
+    }
+}
+
+/// Decodes a batch response, results are returned in the order of the request
+/// IDs. Responses may arrive in any order, missing ones are reported per entry
+pub fn parse_batch_response<'a, C: Encoder, R: Deserialize<'a>>(
+    encoder: &C,
+    buf: &'a [u8],
+    ids: &[u64],
+    compat: &Compat,
+) -> Result<Vec<Result<R, Error>>, Error> {
+    if ids.is_empty() {
+        // notifications only, nothing is returned
+        return Ok(Vec::new());
+    }
+    if buf.is_empty() {
+        return Err(Error::Protocol("empty response"));
+    }
+    let entries: Vec<BatchEntry<R>> = match encoder.decode(buf) {
+        Ok(v) => v,
+        Err(e) => {
+            // the server may reply with a single error if the batch is rejected
+            if let Ok(BatchEntry::<IgnoredAny> {
+                error: Some(err), ..
+            }) = encoder.decode(buf)
+            {
+                return Err(Error::Rpc(err));
+            }
+            return Err(e);
+        }
+    };
+    let positions: BTreeMap<u64, usize> = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+    let mut results: Vec<Option<Result<R, Error>>> = ids.iter().map(|_| None).collect();
+    for entry in entries {
+        compat.check_version(entry.jsonrpc)?;
+        let Some(id) = entry.id else {
+            return Err(entry
+                .error
+                .map_or(Error::Protocol("invalid response ID"), Error::Rpc));
+        };
+        let pos = id
+            .to_u64(compat)
+            .and_then(|id| positions.get(&id))
+            .ok_or(Error::Protocol("invalid response ID"))?;
+        if results[*pos].is_some() {
+            return Err(Error::Protocol("duplicate response ID"));
+        }
+        results[*pos] = Some(select_member(entry.result, entry.error, compat));
+    }
+    Ok(results
+        .into_iter()
+        .map(|r| r.unwrap_or(Err(Error::Protocol("missing response"))))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        encode_request, encode_request_raw, parse_batch_response, parse_response, Compat,
+        IdSequence, ProtocolVersion,
+    };
+    use crate::params::UnitParams;
+    use crate::{Encoder, Error, Json};
+    use serde::Serialize;
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+
+    fn encode<P: Serialize>(params: Option<P>, id: Option<u64>, compat: &Compat) -> Value {
+        let mut buf = Vec::new();
+        encode_request(&Json::default(), "m", params, id, compat, &mut buf).unwrap();
+        serde_json::from_slice(&buf).unwrap()
+    }
+
+    fn parse<R: serde::de::DeserializeOwned>(resp: &str, compat: &Compat) -> Result<R, Error> {
+        parse_response(&Json::default(), resp.as_bytes(), 1, compat)
+    }
+
+    fn protocol_error<R: std::fmt::Debug>(result: Result<R, Error>) -> &'static str {
+        match result {
+            Err(Error::Protocol(msg)) => msg,
+            other => panic!("protocol error expected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let compat = Compat::new();
+        assert_eq!(
+            encode(Some([1, 2]), Some(1), &compat),
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "m", "params": [1, 2] })
+        );
+        // notifications have no ID, omitted params have no member
+        assert_eq!(
+            encode(None::<()>, None, &compat),
+            json!({ "jsonrpc": "2.0", "method": "m" })
+        );
+        assert_eq!(
+            encode(Some(()), Some(1), &compat),
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "m", "params": null })
+        );
+    }
+
+    #[test]
+    fn test_encode_string_ids() {
+        let compat = Compat::new().string_ids();
+        assert_eq!(encode(Some(()), Some(42), &compat)["id"], "42");
+        assert!(encode(Some(()), None, &compat).get("id").is_none());
+    }
+
+    #[test]
+    fn test_encode_v1() {
+        let compat = Compat::new().protocol_version(ProtocolVersion::V1);
+        assert_eq!(
+            encode(Some([1]), Some(1), &compat),
+            json!({ "id": 1, "method": "m", "params": [1] })
+        );
+        // 1.0 notifications have null IDs
+        assert_eq!(
+            encode(Some([1]), None, &compat),
+            json!({ "id": null, "method": "m", "params": [1] })
+        );
+    }
+
+    #[test]
+    fn test_encode_unit_params() {
+        for (unit_params, expected) in [
+            (UnitParams::Null, Some(Value::Null)),
+            (UnitParams::EmptyArray, Some(json!([]))),
+            (UnitParams::EmptyObject, Some(json!({}))),
+            (UnitParams::Omit, None),
+        ] {
+            let compat = Compat::new().unit_params(unit_params);
+            let req = encode(Some(()), Some(1), &compat);
+            assert_eq!(req.get("params"), expected.as_ref());
+            // other params are sent as-is
+            assert_eq!(encode(Some([0]), Some(1), &compat)["params"], json!([0]));
+            assert!(encode(None::<()>, Some(1), &compat).get("params").is_none());
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let compat = Compat::new();
+        assert_eq!(
+            parse::<u64>(r#"{"jsonrpc":"2.0","id":1,"result":42}"#, &compat).unwrap(),
+            42
+        );
+        assert_eq!(
+            protocol_error(parse::<u64>(
+                r#"{"jsonrpc":"2.0","id":2,"result":42}"#,
+                &compat
+            )),
+            "invalid response ID"
+        );
+        assert_eq!(
+            protocol_error(parse::<u64>(r#"{"id":1,"result":42}"#, &compat)),
+            "invalid JSON RPC version"
+        );
+        assert_eq!(
+            protocol_error(parse::<u64>(
+                r#"{"jsonrpc":"1.0","id":1,"result":42}"#,
+                &compat
+            )),
+            "invalid JSON RPC version"
+        );
+        assert!(matches!(
+            parse::<u64>(r#"{"jsonrpc":"2.0","id":1,"result":"x"}"#, &compat),
+            Err(Error::Decode(_))
+        ));
+        assert!(matches!(
+            parse::<u64>(r#"{"jsonrpc":"2.0","id":1,"#, &compat),
+            Err(Error::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_members() {
+        let compat = Compat::new();
+        let Err(Error::Rpc(e)) = parse::<u64>(
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"no method"}}"#,
+            &compat,
+        ) else {
+            panic!("rpc error expected");
+        };
+        assert_eq!(e.code(), -32601);
+        assert_eq!(e.message(), Some("no method"));
+        // null results are accepted for units only
+        parse::<()>(r#"{"jsonrpc":"2.0","id":1,"result":null}"#, &compat).unwrap();
+        assert_eq!(
+            parse::<Option<u64>>(r#"{"jsonrpc":"2.0","id":1,"result":null}"#, &compat).unwrap(),
+            None
+        );
+        assert_eq!(
+            protocol_error(parse::<u64>(
+                r#"{"jsonrpc":"2.0","id":1,"result":null}"#,
+                &compat
+            )),
+            "null result"
+        );
+        assert_eq!(
+            protocol_error(parse::<()>(r#"{"jsonrpc":"2.0","id":1}"#, &compat)),
+            "no result/error fields"
+        );
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        let compat = Compat::new();
+        parse::<()>("", &compat).unwrap();
+        assert_eq!(protocol_error(parse::<u64>("", &compat)), "empty response");
+    }
+
+    #[test]
+    fn test_parse_strict_members() {
+        let both = r#"{"jsonrpc":"2.0","id":1,"result":42,"error":{"code":-32000}}"#;
+        let null_result = r#"{"jsonrpc":"2.0","id":1,"result":null,"error":{"code":-32000}}"#;
+        // the error wins by default
+        for resp in [both, null_result] {
+            assert!(matches!(
+                parse::<u64>(resp, &Compat::new()),
+                Err(Error::Rpc(_))
+            ));
+        }
+        let compat = Compat::new().strict_members();
+        for resp in [both, null_result] {
+            assert_eq!(
+                protocol_error(parse::<u64>(resp, &compat)),
+                "both result and error fields"
+            );
+        }
+        let resp = r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000}}"#;
+        assert!(matches!(parse::<u64>(resp, &compat), Err(Error::Rpc(_))));
+    }
+
+    #[test]
+    fn test_parse_v1() {
+        let compat = Compat::new().protocol_version(ProtocolVersion::V1);
+        assert_eq!(
+            parse::<u64>(r#"{"id":1,"result":42,"error":null}"#, &compat).unwrap(),
+            42
+        );
+        // the version member is not checked
+        assert_eq!(
+            parse::<u64>(r#"{"jsonrpc":"2.0","id":1,"result":42}"#, &compat).unwrap(),
+            42
+        );
+        parse::<()>(r#"{"id":1}"#, &compat).unwrap();
+        // 1.0 errors carry a null result, strict or not
+        for compat in [compat.clone(), compat.clone().strict_members()] {
+            assert!(matches!(
+                parse::<u64>(r#"{"id":1,"result":null,"error":{"code":-1}}"#, &compat),
+                Err(Error::Rpc(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_ids() {
+        let resp = r#"{"jsonrpc":"2.0","id":"1","result":42}"#;
+        assert_eq!(
+            protocol_error(parse::<u64>(resp, &Compat::new())),
+            "invalid response ID"
+        );
+        for compat in [Compat::new().coerce_ids(), Compat::new().string_ids()] {
+            assert_eq!(parse::<u64>(resp, &compat).unwrap(), 42);
+            // numeric IDs are accepted as well
+            assert_eq!(
+                parse::<u64>(r#"{"jsonrpc":"2.0","id":1,"result":42}"#, &compat).unwrap(),
+                42
+            );
+            assert_eq!(
+                protocol_error(parse::<u64>(
+                    r#"{"jsonrpc":"2.0","id":"x1","result":42}"#,
+                    &compat
+                )),
+                "invalid response ID"
+            );
+        }
+    }
+
+    #[test]
+    fn test_id_sequence() {
+        let ids = IdSequence::new();
+        assert_eq!((ids.next(), ids.next(), ids.next()), (0, 1, 2));
+    }
+
+    fn parse_batch(resp: &str, ids: &[u64]) -> Result<Vec<Result<u64, Error>>, Error> {
+        parse_batch_response(&Json::default(), resp.as_bytes(), ids, &Compat::new())
+    }
+
+    #[test]
+    fn test_parse_batch() {
+        let resp = r#"[
+            {"jsonrpc":"2.0","id":3,"result":30},
+            {"jsonrpc":"2.0","id":1,"result":10},
+            {"jsonrpc":"2.0","id":2,"error":{"code":-32601,"message":"no method"}}
+        ]"#;
+        let results = parse_batch(resp, &[1, 2, 3]).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(*results[0].as_ref().unwrap(), 10);
+        assert!(matches!(&results[1], Err(Error::Rpc(e)) if e.code == -32601));
+        assert_eq!(*results[2].as_ref().unwrap(), 30);
+    }
+
+    #[test]
+    fn test_parse_batch_missing() {
+        let resp = r#"[{"jsonrpc":"2.0","id":2,"result":20}]"#;
+        let mut results = parse_batch(resp, &[1, 2, 3]).unwrap().into_iter();
+        assert_eq!(protocol_error(results.next().unwrap()), "missing response");
+        assert_eq!(results.next().unwrap().unwrap(), 20);
+        assert_eq!(protocol_error(results.next().unwrap()), "missing response");
+    }
+
+    #[test]
+    fn test_parse_batch_invalid_ids() {
+        let resp = r#"[
+            {"jsonrpc":"2.0","id":1,"result":10},
+            {"jsonrpc":"2.0","id":1,"result":11}
+        ]"#;
+        assert_eq!(
+            protocol_error(parse_batch(resp, &[1, 2])),
+            "duplicate response ID"
+        );
+        let resp = r#"[{"jsonrpc":"2.0","id":5,"result":50}]"#;
+        assert_eq!(
+            protocol_error(parse_batch(resp, &[1, 2])),
+            "invalid response ID"
+        );
+        let resp = r#"[{"jsonrpc":"2.0","id":null,"result":0}]"#;
+        assert_eq!(
+            protocol_error(parse_batch(resp, &[1])),
+            "invalid response ID"
+        );
+    }
+
+    #[test]
+    fn test_parse_batch_rejected() {
+        // an entry the server could not correlate fails the whole batch
+        let resp = r#"[
+            {"jsonrpc":"2.0","id":1,"result":10},
+            {"jsonrpc":"2.0","id":null,"error":{"code":-32600,"message":"invalid"}}
+        ]"#;
+        assert!(matches!(parse_batch(resp, &[1, 2]), Err(Error::Rpc(e)) if e.code == -32600));
+        // a single error instead of an array
+        let resp = r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"parse"}}"#;
+        assert!(matches!(parse_batch(resp, &[1, 2]), Err(Error::Rpc(e)) if e.code == -32700));
+        assert_eq!(protocol_error(parse_batch("", &[1])), "empty response");
+    }
+
+    #[test]
+    fn test_parse_batch_notifications() {
+        // servers reply to batches of notifications with no content
+        assert!(parse_batch("", &[]).unwrap().is_empty());
+        // responses are correlated by ID, notifications between calls do not
+        // shift them
+        let resp = r#"[{"jsonrpc":"2.0","id":"4","result":40}]"#;
+        let results = parse_batch_response::<_, u64>(
+            &Json::default(),
+            resp.as_bytes(),
+            &[4],
+            &Compat::new().string_ids(),
+        )
+        .unwrap();
+        assert_eq!(*results[0].as_ref().unwrap(), 40);
+    }
+
+    #[derive(Serialize, Clone)]
+    struct Params {
+        name: &'static str,
+        values: Vec<u8>,
+    }
+
+    fn compats() -> Vec<Compat> {
+        vec![
+            Compat::new(),
+            Compat::new().string_ids(),
+            Compat::new().protocol_version(ProtocolVersion::V1),
+            Compat::new().unit_params(UnitParams::Omit),
+            Compat::new().unit_params(UnitParams::EmptyArray),
+            Compat::new().unit_params(UnitParams::EmptyObject),
+        ]
+    }
+
+    // a request with params encoded before is the same as a request encoded
+    // at once
+    fn check_raw<C: Encoder, P: Serialize + Clone>(encoder: &C, params: Option<P>) {
+        for compat in compats() {
+            for id in [Some(7), None] {
+                let mut buf = Vec::new();
+                encode_request(encoder, "m", params.clone(), id, &compat, &mut buf).unwrap();
+                let raw = compat
+                    .unit_params
+                    .apply(params.clone())
+                    .map(|params| encoder.encode(&params).unwrap());
+                let mut raw_buf = Vec::new();
+                encode_request_raw(encoder, "m", raw.as_deref(), id, &compat, &mut raw_buf)
+                    .unwrap();
+                assert_eq!(buf, raw_buf);
+            }
+        }
+    }
+
+    fn check_raw_all<C: Encoder>(encoder: &C) {
+        check_raw(
+            encoder,
+            Some(Params {
+                name: "test",
+                values: vec![1, 2, 3],
+            }),
+        );
+        check_raw(encoder, Some(()));
+        check_raw(encoder, None::<()>);
+        check_raw(encoder, Some(BTreeMap::from([(1u8, "a"), (2, "b")])));
+        check_raw(encoder, Some((1, "null")));
+    }
+
+    #[test]
+    fn test_raw_params() {
+        check_raw_all(&Json::default());
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_raw_params_msgpack() {
+        use crate::MsgPack;
+        check_raw_all(&MsgPack::default());
+        check_raw_all(&MsgPack::compact());
+        // keys which are not strings
+        check_raw(
+            &MsgPack::default(),
+            Some(BTreeMap::from([((1u8, 2u8), "a")])),
+        );
+    }
+
+    #[cfg(feature = "msgpack")]
+    type Envelope = (Option<String>, Option<u64>, String, Option<Vec<u64>>);
+
+    #[cfg(feature = "msgpack")]
+    fn encode_compact_envelope(
+        params: Option<Vec<u64>>,
+        id: Option<u64>,
+        compat: &Compat,
+    ) -> Envelope {
+        let mut buf = Vec::new();
+        encode_request(
+            &crate::MsgPack::compact(),
+            "m",
+            params,
+            id,
+            compat,
+            &mut buf,
+        )
+        .unwrap();
+        rmp_serde::from_slice(&buf).unwrap()
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_compact_envelope() {
+        let encode = encode_compact_envelope;
+        let compat = Compat::new();
+        assert_eq!(
+            encode(Some(vec![7]), Some(1), &compat),
+            (
+                Some("2.0".to_owned()),
+                Some(1),
+                "m".to_owned(),
+                Some(vec![7])
+            )
+        );
+        assert_eq!(
+            encode(Some(vec![7]), None, &compat),
+            (Some("2.0".to_owned()), None, "m".to_owned(), Some(vec![7]))
+        );
+        assert_eq!(
+            encode(None, Some(1), &compat),
+            (Some("2.0".to_owned()), Some(1), "m".to_owned(), None)
+        );
+        let compat = Compat::new().protocol_version(ProtocolVersion::V1);
+        assert_eq!(
+            encode(None, None, &compat),
+            (None, None, "m".to_owned(), None)
+        );
+    }
+}
@@ -0,0 +1,404 @@
+use crate::protocol::parse_accepted_response;
+use crate::{CallOpts, Encoder, Error, HttpClient, Json, Rpc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Recorded calls, stored as JSON (or MessagePack for .msgpack files with
+/// "msgpack" feature)
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Cassette {
+    calls: Vec<RecordedCall>,
+}
+
+// responses are kept as received and decoded on replay, so results do not
+// pass through self-describing values
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct RecordedCall {
+    method: String,
+    params: Value,
+    encoder: String,
+    response: Body,
+    latency_ms: u64,
+}
+
+// text bodies (e.g. JSON) are stored as strings to keep cassettes readable
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum Body {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Body {
+    fn new(data: Vec<u8>) -> Self {
+        match String::from_utf8(data) {
+            Ok(text) => Body::Text(text),
+            Err(e) => Body::Binary(e.into_bytes()),
+        }
+    }
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Body::Text(text) => text.as_bytes(),
+            Body::Binary(data) => data,
+        }
+    }
+}
+
+impl Cassette {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let data = std::fs::read(path.as_ref())?;
+        #[cfg(feature = "msgpack")]
+        if is_msgpack(path.as_ref()) {
+            return Ok(rmp_serde::from_slice(&data)?);
+        }
+        Ok(serde_json::from_slice(&data)?)
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        #[cfg(feature = "msgpack")]
+        if is_msgpack(path.as_ref()) {
+            std::fs::write(path, rmp_serde::to_vec_named(self)?)?;
+            return Ok(());
+        }
+        let data = serde_json::to_vec_pretty(self).map_err(|e| Error::Encode(e.into()))?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "msgpack")]
+fn is_msgpack(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "msgpack")
+}
+
+/// A response body with the name of the encoder it has been decoded with
+#[derive(Clone, Debug)]
+pub struct RawResponse {
+    body: Vec<u8>,
+    encoder: &'static str,
+}
+
+impl RawResponse {
+    #[inline]
+    pub fn new(body: Vec<u8>, encoder: &'static str) -> Self {
+        Self { body, encoder }
+    }
+}
+
+/// Clients which can be recorded
+pub trait RecordRpc: Rpc {
+    /// Calls a method, returns the result and the response it has been
+    /// decoded from (if the server replied with a result or an RPC error)
+    fn call_recorded<P, R>(
+        &self,
+        method: &str,
+        params: P,
+    ) -> (Result<R, Error>, Option<RawResponse>)
+    where
+        P: Serialize,
+        R: DeserializeOwned;
+}
+
+impl<T: RecordRpc + ?Sized> RecordRpc for &T {
+    #[inline]
+    fn call_recorded<P, R>(
+        &self,
+        method: &str,
+        params: P,
+    ) -> (Result<R, Error>, Option<RawResponse>)
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
+        (**self).call_recorded(method, params)
+    }
+}
+
+impl<T: RecordRpc + ?Sized> RecordRpc for Arc<T> {
+    #[inline]
+    fn call_recorded<P, R>(
+        &self,
+        method: &str,
+        params: P,
+    ) -> (Result<R, Error>, Option<RawResponse>)
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
+        (**self).call_recorded(method, params)
+    }
+}
+
+impl<C> RecordRpc for HttpClient<C>
+where
+    C: Encoder,
+{
+    fn call_recorded<P, R>(
+        &self,
+        method: &str,
+        params: P,
+    ) -> (Result<R, Error>, Option<RawResponse>)
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
+        let body = RefCell::new(None);
+        let result = self.call_with(method, Some(params), &CallOpts::default(), |_, buf, id| {
+            let result = self.parse_result(buf, id);
+            if matches!(result, Ok(_) | Err(Error::Rpc(_))) {
+                body.replace(Some(buf.to_vec()));
+            }
+            result
+        });
+        let raw = body
+            .into_inner()
+            .map(|body| RawResponse::new(body, self.encoder.name()));
+        (result, raw)
+    }
+}
+
+/// Wraps a client and records calls and their responses to a cassette
+///
+/// Transport errors are not recorded.
+pub struct Recorder<T: RecordRpc> {
+    client: T,
+    cassette: Mutex<Cassette>,
+}
+
+impl<T: RecordRpc> Recorder<T> {
+    #[inline]
+    pub fn new(client: T) -> Self {
+        Self {
+            client,
+            cassette: <_>::default(),
+        }
+    }
+    #[inline]
+    pub fn cassette(&self) -> Cassette {
+        self.cassette.lock().unwrap().clone()
+    }
+    #[inline]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        self.cassette.lock().unwrap().save(path)
+    }
+}
+
+impl<T: RecordRpc> Rpc for Recorder<T> {
+    fn call<P, R>(&self, method: &str, params: P) -> Result<R, Error>
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
+        let params_value = serde_json::to_value(&params).map_err(|e| Error::Encode(e.into()))?;
+        let started = Instant::now();
+        let (result, raw) = self.client.call_recorded(method, params);
+        if let (Ok(_) | Err(Error::Rpc(_)), Some(raw)) = (&result, raw) {
+            let call = RecordedCall {
+                method: method.to_owned(),
+                params: params_value,
+                encoder: raw.encoder.to_owned(),
+                response: Body::new(raw.body),
+                latency_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+            };
+            self.cassette.lock().unwrap().calls.push(call);
+        }
+        result
+    }
+}
+
+impl RecordedCall {
+    fn output<R: DeserializeOwned>(&self) -> Result<R, Error> {
+        let buf = self.response.as_bytes();
+        match self.encoder.as_str() {
+            "json" => parse_accepted_response(&Json::default(), buf),
+            #[cfg(feature = "msgpack")]
+            "msgpack" => parse_accepted_response(&crate::MsgPack::default(), buf),
+            #[cfg(feature = "msgpack")]
+            "msgpack-compact" => parse_accepted_response(&crate::MsgPack::compact(), buf),
+            encoder => Err(Error::Other(
+                format!("unsupported cassette encoder {}", encoder).into(),
+            )),
+        }
+    }
+}
+
+/// A client which serves calls from a cassette, calls are matched by method
+/// and params
+///
+/// Identical calls are served in the recorded order, the last one is repeated
+/// when all are used.
+pub struct Replay {
+    cassette: Cassette,
+    used: Mutex<Vec<bool>>,
+    simulate_latency: bool,
+}
+
+impl Replay {
+    #[inline]
+    pub fn new(cassette: Cassette) -> Self {
+        Self {
+            used: Mutex::new(vec![false; cassette.calls.len()]),
+            cassette,
+            simulate_latency: false,
+        }
+    }
+    #[inline]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Cassette::load(path).map(Self::new)
+    }
+    /// Delays replies for the recorded call duration
+    #[inline]
+    pub fn simulate_latency(mut self) -> Self {
+        self.simulate_latency = true;
+        self
+    }
+}
+
+impl Rpc for Replay {
+    fn call<P, R>(&self, method: &str, params: P) -> Result<R, Error>
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
+        let params = serde_json::to_value(params).map_err(|e| Error::Encode(e.into()))?;
+        let call = {
+            let mut used = self.used.lock().unwrap();
+            let mut last = None;
+            let mut found = None;
+            for (i, call) in self.cassette.calls.iter().enumerate() {
+                if call.method == method && call.params == params {
+                    if !used[i] {
+                        found = Some(i);
+                        break;
+                    }
+                    last = Some(i);
+                }
+            }
+            let Some(i) = found.or(last) else {
+                return Err(Error::Other(
+                    format!("no recorded call for method {}", method).into(),
+                ));
+            };
+            used[i] = true;
+            &self.cassette.calls[i]
+        };
+        if self.simulate_latency {
+            std::thread::sleep(Duration::from_millis(call.latency_ms));
+        }
+        call.output()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Body, Cassette, RecordedCall, Recorder, Replay};
+    use crate::test_support::{serve, Response};
+    use crate::{http_client, Error, ErrorKind, Rpc};
+    use serde::Deserialize;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Account {
+        name: String,
+        balance: u64,
+    }
+
+    fn serve_accounts() -> String {
+        serve(|req| {
+            let call: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+            match call["params"]["name"].as_str().unwrap() {
+                "missing" => Response::error(req, -32001),
+                name => Response::result(req, json!({ "name": name, "balance": u64::MAX })),
+            }
+        })
+    }
+
+    #[test]
+    fn test_record_replay() {
+        let recorder = Recorder::new(http_client(&serve_accounts()));
+        let account: Account = recorder.call("get", json!({ "name": "alice" })).unwrap();
+        assert_eq!(account.balance, u64::MAX);
+        let err = recorder
+            .call::<_, Account>("get", json!({ "name": "missing" }))
+            .unwrap_err();
+        assert!(matches!(err, Error::Rpc(e) if e.code() == -32001));
+        let path = std::env::temp_dir().join(format!("bma-jrpc-{}.json", std::process::id()));
+        recorder.save(&path).unwrap();
+        let replay = Replay::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let replayed: Account = replay.call("get", json!({ "name": "alice" })).unwrap();
+        assert_eq!(replayed, account);
+        // served again, the last matching call is repeated
+        let replayed: Account = replay.call("get", json!({ "name": "alice" })).unwrap();
+        assert_eq!(replayed, account);
+        let err = replay
+            .call::<_, Account>("get", json!({ "name": "missing" }))
+            .unwrap_err();
+        assert!(matches!(err, Error::Rpc(e) if e.code() == -32001));
+        let err = replay
+            .call::<_, Account>("get", json!({ "name": "bob" }))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_transport_error_not_recorded() {
+        let recorder = Recorder::new(http_client("http://127.0.0.1:1"));
+        let err = recorder.call::<_, ()>("test", ()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        assert!(recorder.cassette().is_empty());
+    }
+
+    #[test]
+    fn test_output() {
+        let call = |encoder: &str, response: &str| RecordedCall {
+            method: "test".to_owned(),
+            params: json!(null),
+            encoder: encoder.to_owned(),
+            response: Body::new(response.as_bytes().to_vec()),
+            latency_ms: 0,
+        };
+        assert!(matches!(
+            call("json", r#"{"jsonrpc":"2.0","id":1,"result":[1,2]}"#).output::<Vec<u8>>(),
+            Ok(v) if v == [1, 2]
+        ));
+        // no result member is accepted for methods which return nothing
+        call("json", r#"{"id":1}"#).output::<()>().unwrap();
+        call("json", "").output::<()>().unwrap();
+        let err = call("json", r#"{"id":1,"result":"x"}"#)
+            .output::<u8>()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Decode);
+        let err = call("custom", r#"{"id":1}"#).output::<()>().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Other);
+        assert!(matches!(Body::new(vec![0xc0, 0xff]), Body::Binary(_)));
+    }
+
+    #[test]
+    fn test_params_encode_error() {
+        // JSON maps require string keys
+        let params = BTreeMap::from([((1, 2), 3)]);
+        let recorder = Recorder::new(http_client("http://127.0.0.1:1"));
+        let err = recorder.call::<_, ()>("test", &params).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Encode);
+        let replay = Replay::new(Cassette::new());
+        let err = replay.call::<_, ()>("test", &params).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Encode);
+    }
+}
@@ -0,0 +1,44 @@
+//! Process-global registry of named clients
+//!
+//! Allows plugins and other loosely coupled code to resolve clients at
+//! runtime without passing handles through every layer.
+//!
+//! ```rust,no_run
+//! use bma_jrpc::{http_client, registry, Json, HttpClient};
+//!
+//! registry::set("core", http_client("http://localhost:7727"));
+//! // somewhere else
+//! let client = registry::get::<HttpClient<Json>>("core").unwrap();
+//! ```
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+type Clients = BTreeMap<String, Arc<dyn Any + Send + Sync>>;
+
+static CLIENTS: RwLock<Clients> = RwLock::new(BTreeMap::new());
+
+/// Registers a client under the name, replacing the previous one
+pub fn set<T: Send + Sync + 'static>(name: &str, client: T) {
+    CLIENTS
+        .write()
+        .unwrap()
+        .insert(name.to_owned(), Arc::new(client));
+}
+
+/// Returns the client registered under the name, `None` if there is no such
+/// client or it has a different type
+pub fn get<T: Send + Sync + 'static>(name: &str) -> Option<Arc<T>> {
+    let client = CLIENTS.read().unwrap().get(name)?.clone();
+    client.downcast().ok()
+}
+
+/// Unregisters a client, returns true if it was registered
+pub fn remove(name: &str) -> bool {
+    CLIENTS.write().unwrap().remove(name).is_some()
+}
+
+/// Names of the registered clients
+pub fn names() -> Vec<String> {
+    CLIENTS.read().unwrap().keys().cloned().collect()
+}
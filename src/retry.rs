@@ -0,0 +1,402 @@
+use crate::{Error, ErrorKind, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(100);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(5);
+const DEFAULT_BUDGET_MIN_RETRIES: u32 = 10;
+// the budget balance is capped to the deposits of this number of calls
+const BUDGET_CAP_CALLS: f64 = 100.0;
+
+/// Per-call retry policy
+///
+/// Calls are retried on transport errors (timeouts, refused connections, I/O
+/// and TLS handshake errors), checksum mismatches, HTTP 502, 503 and 504
+/// responses and the RPC error codes set with [`RetryPolicy::rpc_codes`].
+/// Delays between attempts grow exponentially. Attempts send the same encoded request with the same ID
+/// unless [`RetryPolicy::new_id_per_attempt`] is set.
+#[derive(Clone, Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct RetryPolicy {
+    retries: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    new_id_per_attempt: bool,
+    rpc_codes: Vec<i16>,
+}
+
+impl RetryPolicy {
+    #[inline]
+    pub fn new(retries: u32) -> Self {
+        Self {
+            retries,
+            backoff: DEFAULT_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            new_id_per_attempt: false,
+            rpc_codes: Vec::new(),
+        }
+    }
+    #[inline]
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+    /// Sends each attempt with a new request ID, for servers which reject
+    /// reused IDs. Params are encoded once, retries wrap them into a request
+    /// with the new ID when made
+    #[inline]
+    pub fn new_id_per_attempt(mut self) -> Self {
+        self.new_id_per_attempt = true;
+        self
+    }
+    /// Also retries calls which fail with the RPC error codes, e.g. -32005 of
+    /// servers which report overload in the response. Other RPC errors are
+    /// never retried
+    #[inline]
+    pub fn rpc_codes(mut self, codes: &[i16]) -> Self {
+        self.rpc_codes = codes.to_vec();
+        self
+    }
+    #[inline]
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+    #[inline]
+    pub(crate) fn renews_id(&self) -> bool {
+        self.new_id_per_attempt
+    }
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        self.backoff
+            .checked_mul(2_u32.saturating_pow(attempt))
+            .map_or(self.max_backoff, |d| d.min(self.max_backoff))
+    }
+    #[inline]
+    pub(crate) fn is_retryable(&self, err: &Error) -> bool {
+        match err {
+            Error::Rpc(e) => self.rpc_codes.contains(&e.code()),
+            _ => is_transient(err),
+        }
+    }
+}
+
+/// I/O errors, timeouts, failed connections and TLS handshakes, corrupted
+/// bodies and HTTP 502, 503 and 504, the server may be reachable later
+pub(crate) fn is_transient(err: &Error) -> bool {
+    match err.kind() {
+        ErrorKind::Timeout
+        | ErrorKind::ConnectionRefused
+        | ErrorKind::Transport
+        | ErrorKind::Integrity => true,
+        // certificate errors are not fixed by retrying
+        ErrorKind::Tls => matches!(
+            err,
+            Error::Transport(e) if e.kind() == isahc::error::ErrorKind::TlsEngine
+        ),
+        ErrorKind::Http => matches!(
+            err,
+            Error::Http(
+                StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT,
+                _
+            )
+        ),
+        _ => false,
+    }
+}
+
+/// Limits retries of a client to a ratio of the calls made
+///
+/// Every call deposits `ratio` to the budget, every retry withdraws one. A
+/// reserve of `min_retries` allows retrying when the client is idle. With the
+/// ratio of 0.2 retries can not add more than 20% of extra load.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct RetryBudget {
+    ratio: f64,
+    min_retries: u32,
+    state: Mutex<BudgetState>,
+}
+
+#[derive(Debug)]
+struct BudgetState {
+    balance: f64,
+    retries: u64,
+    exhausted: u64,
+}
+
+/// Retry budget state, can be saved and restored with
+/// [`RetryBudget::restore`], e.g. by short-lived processes
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[allow(clippy::module_name_repetitions)]
+pub struct RetryBudgetStats {
+    pub balance: f64,
+    pub retries: u64,
+    pub exhausted: u64,
+}
+
+impl RetryBudget {
+    #[inline]
+    pub fn new(ratio: f64) -> Self {
+        Self::with_min_retries(ratio, DEFAULT_BUDGET_MIN_RETRIES)
+    }
+    #[inline]
+    pub fn with_min_retries(ratio: f64, min_retries: u32) -> Self {
+        Self {
+            ratio,
+            min_retries,
+            state: Mutex::new(BudgetState {
+                balance: f64::from(min_retries),
+                retries: 0,
+                exhausted: 0,
+            }),
+        }
+    }
+    /// Restores the state saved from [`RetryBudget::stats`], the balance is
+    /// capped as usual
+    pub fn restore(self, stats: RetryBudgetStats) -> Self {
+        let cap = self.cap();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.balance = stats.balance.clamp(0.0, cap);
+            state.retries = stats.retries;
+            state.exhausted = stats.exhausted;
+        }
+        self
+    }
+    #[inline]
+    pub(crate) fn params(&self) -> (f64, u32) {
+        (self.ratio, self.min_retries)
+    }
+    #[inline]
+    fn cap(&self) -> f64 {
+        f64::from(self.min_retries) + self.ratio * BUDGET_CAP_CALLS
+    }
+    pub(crate) fn deposit(&self) {
+        let cap = self.cap();
+        let mut state = self.state.lock().unwrap();
+        state.balance = (state.balance + self.ratio).min(cap);
+    }
+    pub(crate) fn withdraw(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.balance >= 1.0 {
+            state.balance -= 1.0;
+            state.retries += 1;
+            true
+        } else {
+            state.exhausted += 1;
+            false
+        }
+    }
+    pub fn stats(&self) -> RetryBudgetStats {
+        let state = self.state.lock().unwrap();
+        RetryBudgetStats {
+            balance: state.balance,
+            retries: state.retries,
+            exhausted: state.exhausted,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RetryBudget, RetryPolicy};
+    use crate::test_support::{self, serve_replies as serve, Reply, Response};
+    use crate::{http_client, CallOpts, Compat, Error, ErrorKind, Rpc, TestClock, UnitParams};
+    use futures_lite::future;
+    use std::sync::atomic::Ordering;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    fn ms(v: &[u64]) -> Vec<Duration> {
+        v.iter().copied().map(Duration::from_millis).collect()
+    }
+
+    #[test]
+    fn test_backoff() {
+        let (url, requests) = serve(vec![
+            Reply::Status(503),
+            Reply::Status(502),
+            Reply::Status(504),
+            Reply::Ok,
+        ]);
+        let clock = Arc::new(TestClock::new());
+        let client = http_client(&url)
+            .retry(
+                RetryPolicy::new(5).backoff(Duration::from_millis(100), Duration::from_millis(250)),
+            )
+            .clock(clock.clone());
+        let result: usize = client.call("test", ()).unwrap();
+        assert_eq!(result, 3);
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+        assert_eq!(clock.sleeps(), ms(&[100, 200, 250]));
+        assert_eq!(clock.elapsed(), Duration::from_millis(550));
+    }
+
+    #[test]
+    fn test_backoff_async() {
+        let (url, requests) = serve(vec![Reply::Status(503), Reply::Status(503), Reply::Ok]);
+        let clock = Arc::new(TestClock::new());
+        let client = http_client(&url)
+            .retry(RetryPolicy::new(5))
+            .clock(clock.clone());
+        let result: usize = future::block_on(client.call_async("test", ())).unwrap();
+        assert_eq!(result, 2);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert_eq!(clock.sleeps(), ms(&[100, 200]));
+    }
+
+    #[test]
+    fn test_retries_exhausted() {
+        let (url, requests) = serve(vec![Reply::Status(503)]);
+        let clock = Arc::new(TestClock::new());
+        let client = http_client(&url)
+            .retry(RetryPolicy::new(2))
+            .clock(clock.clone());
+        let err = client.call::<_, usize>("test", ()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Http);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert_eq!(clock.sleeps(), ms(&[100, 200]));
+    }
+
+    #[test]
+    fn test_http_error_not_retried() {
+        let (url, requests) = serve(vec![Reply::Status(500), Reply::Ok]);
+        let clock = Arc::new(TestClock::new());
+        let client = http_client(&url)
+            .retry(RetryPolicy::new(3))
+            .clock(clock.clone());
+        let err = client.call::<_, usize>("test", ()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Http);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(clock.sleeps().is_empty());
+    }
+
+    #[test]
+    fn test_budget_exhausted() {
+        let (url, requests) = serve(vec![Reply::Status(503)]);
+        let clock = Arc::new(TestClock::new());
+        let client = http_client(&url)
+            .retry(RetryPolicy::new(3))
+            .retry_budget(RetryBudget::with_min_retries(0.0, 1))
+            .clock(clock.clone());
+        let err = client.call::<_, usize>("test", ()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::RetryBudgetExhausted);
+        let Error::RetryBudgetExhausted(last) = err else {
+            unreachable!()
+        };
+        assert_eq!(last.kind(), ErrorKind::Http);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(clock.sleeps(), ms(&[100]));
+        let stats = client.retry_budget_stats().unwrap();
+        assert_eq!(stats.retries, 1);
+        assert_eq!(stats.exhausted, 1);
+        // the budget is empty, the next call is not retried at all
+        client.call::<_, usize>("test", ()).unwrap_err();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert_eq!(clock.sleeps(), ms(&[100]));
+    }
+
+    #[test]
+    fn test_rpc_codes() {
+        let (url, requests) = serve(vec![Reply::RpcError(-32005), Reply::Ok]);
+        let clock = Arc::new(TestClock::new());
+        let client = http_client(&url)
+            .retry(RetryPolicy::new(3).rpc_codes(&[-32005]))
+            .clock(clock.clone());
+        let result: usize = client.call("test", ()).unwrap();
+        assert_eq!(result, 1);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(clock.sleeps(), ms(&[100]));
+    }
+
+    #[test]
+    fn test_rpc_codes_not_matched() {
+        let (url, requests) = serve(vec![Reply::RpcError(-32602), Reply::Ok]);
+        let clock = Arc::new(TestClock::new());
+        let client = http_client(&url)
+            .retry(RetryPolicy::new(3).rpc_codes(&[-32005]))
+            .clock(clock.clone());
+        let err = client.call::<_, usize>("test", ()).unwrap_err();
+        let Error::Rpc(e) = err else {
+            panic!("unexpected error: {}", err);
+        };
+        assert_eq!(e.code(), -32602);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(clock.sleeps().is_empty());
+    }
+
+    /// Fails the first two requests with 503, returns the bodies received
+    fn serve_bodies() -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let received = bodies.clone();
+        let url = test_support::serve(move |req| {
+            let mut bodies = received.lock().unwrap();
+            bodies.push(serde_json::from_slice(&req.body).unwrap());
+            if bodies.len() < 3 {
+                Response::status(503)
+            } else {
+                Response::result(req, true.into())
+            }
+        });
+        (url, bodies)
+    }
+
+    #[test]
+    fn test_new_id_per_attempt() {
+        let (url, bodies) = serve_bodies();
+        let client = http_client(&url)
+            .retry(RetryPolicy::new(3).new_id_per_attempt())
+            .clock(Arc::new(TestClock::new()));
+        let params = serde_json::json!({ "a": [1, 2], "b": null });
+        assert!(future::block_on(client.call_async::<_, bool>("test", &params)).unwrap());
+        let bodies = bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 3);
+        for (id, body) in bodies.iter().enumerate() {
+            assert_eq!(body["id"], id);
+            assert_eq!(body["method"], "test");
+            assert_eq!(body["params"], params);
+        }
+    }
+
+    #[test]
+    fn test_new_id_per_attempt_no_params() {
+        let (url, bodies) = serve_bodies();
+        let client = http_client(&url)
+            .retry(RetryPolicy::new(3).new_id_per_attempt())
+            .compat(Compat::new().unit_params(UnitParams::EmptyArray))
+            .clock(Arc::new(TestClock::new()));
+        assert!(future::block_on(client.call_async_no_params::<bool>("test")).unwrap());
+        let bodies = bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 3);
+        for body in bodies.iter() {
+            assert!(body.get("params").is_none(), "{}", body);
+        }
+        drop(bodies);
+        // unit params are converted once, the same way for all attempts
+        let (url, bodies) = serve_bodies();
+        let client = http_client(&url)
+            .retry(RetryPolicy::new(3).new_id_per_attempt())
+            .compat(Compat::new().unit_params(UnitParams::EmptyArray))
+            .clock(Arc::new(TestClock::new()));
+        assert!(client
+            .call_with_opts::<_, bool>("test", (), &CallOpts::new())
+            .unwrap());
+        for body in bodies.lock().unwrap().iter() {
+            assert_eq!(body["params"], serde_json::json!([]));
+        }
+    }
+
+    #[test]
+    fn test_invalid_request_not_retried() {
+        let clock = Arc::new(TestClock::new());
+        let client = http_client("http://[bad")
+            .retry(RetryPolicy::new(3))
+            .clock(clock.clone());
+        let err = client.call::<_, ()>("test", ()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidRequest);
+        assert!(clock.sleeps().is_empty());
+    }
+}
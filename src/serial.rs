@@ -0,0 +1,440 @@
+use crate::protocol::{encode_request, parse_response, Compat, IdSequence};
+use crate::{Encoder, Error, Rpc, DEFAULT_TIMEOUT};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum Framing {
+    /// Frames are terminated with a new line, suitable for JSON only
+    #[default]
+    Newline,
+    /// RFC 1055 SLIP framing, suitable for binary encoders
+    Slip,
+}
+
+impl Framing {
+    fn write_frame(self, payload: &[u8], buf: &mut Vec<u8>) {
+        match self {
+            Framing::Newline => {
+                buf.extend(payload);
+                buf.push(b'\n');
+            }
+            Framing::Slip => {
+                buf.push(SLIP_END);
+                for &b in payload {
+                    match b {
+                        SLIP_END => buf.extend([SLIP_ESC, SLIP_ESC_END]),
+                        SLIP_ESC => buf.extend([SLIP_ESC, SLIP_ESC_ESC]),
+                        _ => buf.push(b),
+                    }
+                }
+                buf.push(SLIP_END);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct FrameReader {
+    frame: Vec<u8>,
+    escaped: bool,
+}
+
+impl FrameReader {
+    /// Returns true when a complete non-empty frame is collected
+    fn push(&mut self, framing: Framing, b: u8) -> bool {
+        match framing {
+            Framing::Newline => match b {
+                b'\n' => {
+                    if self.frame.last() == Some(&b'\r') {
+                        self.frame.pop();
+                    }
+                    return !self.frame.is_empty();
+                }
+                _ => self.frame.push(b),
+            },
+            Framing::Slip => {
+                if self.escaped {
+                    self.escaped = false;
+                    self.frame.push(match b {
+                        SLIP_ESC_END => SLIP_END,
+                        SLIP_ESC_ESC => SLIP_ESC,
+                        _ => b,
+                    });
+                } else {
+                    match b {
+                        SLIP_END => return !self.frame.is_empty(),
+                        SLIP_ESC => self.escaped = true,
+                        _ => self.frame.push(b),
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+/// JSON RPC client for devices connected to a serial port (RS-232/RS-485)
+///
+/// Calls are serialized, a single request is in flight at any time.
+pub struct SerialClient<C>
+where
+    C: Encoder,
+{
+    port: Mutex<File>,
+    req_id: IdSequence,
+    timeout: Duration,
+    max_frame_size: usize,
+    framing: Framing,
+    compat: Compat,
+    encoder: C,
+}
+
+impl<C> SerialClient<C>
+where
+    C: Encoder,
+{
+    /// Opens a serial port device (e.g. /dev/ttyUSB0) in raw 8N1 mode
+    pub fn open(path: &str, baud: u32) -> Result<Self, Error> {
+        let port = OpenOptions::new()
+            .read(true)
+            .write(true)
+            // do not wait for the carrier detect
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(path)
+            .map_err(transport_err)?;
+        configure_port(&port, baud).map_err(transport_err)?;
+        Ok(Self {
+            port: Mutex::new(port),
+            req_id: IdSequence::new(),
+            timeout: DEFAULT_TIMEOUT,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            framing: Framing::default(),
+            compat: Compat::default(),
+            encoder: C::default(),
+        })
+    }
+    #[inline]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// Replies larger than the limit fail the call (default: 1 MiB)
+    #[inline]
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+    #[inline]
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+    #[inline]
+    pub fn compat(mut self, compat: Compat) -> Self {
+        self.compat = compat;
+        self
+    }
+    #[inline]
+    pub fn encoder(mut self, encoder: C) -> Self {
+        self.encoder = encoder;
+        self
+    }
+    /// Calls a method with the params member omitted (not null or empty)
+    pub fn call_no_params<R>(&self, method: &str) -> Result<R, Error>
+    where
+        R: DeserializeOwned,
+    {
+        self.call_with(method, None::<()>, self.timeout)
+    }
+    /// Calls a method with a timeout other than the client one, e.g. for
+    /// methods which take long on the device side
+    pub fn call_timeout<P, R>(&self, method: &str, params: P, timeout: Duration) -> Result<R, Error>
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
+        self.call_with(method, Some(params), timeout)
+    }
+    fn call_with<P, R>(
+        &self,
+        method: &str,
+        params: Option<P>,
+        timeout: Duration,
+    ) -> Result<R, Error>
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
+        let id = self.req_id.next();
+        let mut payload = Vec::new();
+        encode_request(
+            &self.encoder,
+            method,
+            params,
+            Some(id),
+            &self.compat,
+            &mut payload,
+        )?;
+        let mut frame = Vec::with_capacity(payload.len() + 2);
+        self.framing.write_frame(&payload, &mut frame);
+        let mut port = self.port.lock().unwrap();
+        // drop replies of timed out calls
+        flush_input(&port).map_err(transport_err)?;
+        port.write_all(&frame).map_err(transport_err)?;
+        let mut reader = FrameReader::default();
+        read_frame(
+            &mut port,
+            &mut reader,
+            self.framing,
+            timeout,
+            self.max_frame_size,
+        )?;
+        parse_response(&self.encoder, &reader.frame, id, &self.compat)
+    }
+}
+
+impl<C> Rpc for SerialClient<C>
+where
+    C: Encoder,
+{
+    fn call<P, R>(&self, method: &str, params: P) -> Result<R, Error>
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
+        self.call_with(method, Some(params), self.timeout)
+    }
+}
+
+fn read_frame(
+    port: &mut File,
+    reader: &mut FrameReader,
+    framing: Framing,
+    timeout: Duration,
+    max_frame_size: usize,
+) -> Result<(), Error> {
+    // no deadline if it overflows
+    let deadline = Instant::now().checked_add(timeout);
+    let mut buf = [0_u8; 256];
+    loop {
+        let remaining = deadline.map_or(timeout, |d| d.saturating_duration_since(Instant::now()));
+        if remaining.is_zero() || !wait_readable(port, remaining).map_err(transport_err)? {
+            return Err(transport_err(io::ErrorKind::TimedOut.into()));
+        }
+        let n = match port.read(&mut buf) {
+            // the device is gone (e.g. a USB adapter is unplugged), poll
+            // reports such ports as readable forever
+            Ok(0) => return Err(transport_err(io::ErrorKind::UnexpectedEof.into())),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(transport_err(e)),
+        };
+        for &b in &buf[..n] {
+            if reader.push(framing, b) {
+                return Ok(());
+            }
+            if reader.frame.len() > max_frame_size {
+                return Err(Error::Protocol("frame too large"));
+            }
+        }
+    }
+}
+
+#[inline]
+fn transport_err(e: io::Error) -> Error {
+    Error::Transport(e.into())
+}
+
+fn baud_rate(baud: u32) -> Option<libc::speed_t> {
+    Some(match baud {
+        1200 => libc::B1200,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115_200 => libc::B115200,
+        230_400 => libc::B230400,
+        _ => return None,
+    })
+}
+
+fn configure_port(port: &File, baud: u32) -> io::Result<()> {
+    let speed = baud_rate(baud)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unsupported baud rate"))?;
+    let fd = port.as_raw_fd();
+    // SAFETY: fd is a valid open descriptor, termios is fully initialized by tcgetattr
+    unsafe {
+        let mut tio: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut tio) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::cfmakeraw(&mut tio);
+        tio.c_cflag |= libc::CLOCAL | libc::CREAD;
+        tio.c_cflag &= !(libc::CSTOPB | libc::PARENB);
+        if libc::cfsetispeed(&mut tio, speed) != 0
+            || libc::cfsetospeed(&mut tio, speed) != 0
+            || libc::tcsetattr(fd, libc::TCSANOW, &tio) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        // blocking writes, reads are guarded with poll
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn flush_input(port: &File) -> io::Result<()> {
+    // SAFETY: the descriptor is owned by port
+    if unsafe { libc::tcflush(port.as_raw_fd(), libc::TCIFLUSH) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+fn wait_readable(port: &File, timeout: Duration) -> io::Result<bool> {
+    let mut pfd = libc::pollfd {
+        fd: port.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout_ms = libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX);
+    loop {
+        // SAFETY: pfd is a valid pollfd structure
+        match unsafe { libc::poll(&mut pfd, 1, timeout_ms.max(1)) } {
+            0 => return Ok(false),
+            n if n > 0 => return Ok(true),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_frame, FrameReader, Framing};
+    use crate::Error;
+    use std::fs::File;
+    use std::io::{self, Write};
+    use std::os::unix::io::FromRawFd;
+    use std::time::Duration;
+
+    const PAYLOADS: &[&[u8]] = &[
+        b"{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":42}",
+        &[0xC0, 0xDB, 0x00, 0xDC, 0xDD, 0xC0, 0xC0, 0xDB],
+        &[0x93, 0x01, 0xC0],
+    ];
+
+    fn read_frames(framing: Framing, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut reader = FrameReader::default();
+        let mut frames = Vec::new();
+        for &b in data {
+            if reader.push(framing, b) {
+                frames.push(std::mem::take(&mut reader.frame));
+            }
+        }
+        frames
+    }
+
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        // SAFETY: fds is a valid array of two descriptors
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        // SAFETY: the descriptors are just opened and owned by the files only
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+    }
+
+    fn read_pipe(data: &[u8], close: bool, max_frame_size: usize) -> Result<Vec<u8>, Error> {
+        let (mut rx, mut tx) = pipe();
+        tx.write_all(data).unwrap();
+        if close {
+            drop(tx);
+        } else {
+            std::mem::forget(tx);
+        }
+        let mut reader = FrameReader::default();
+        read_frame(
+            &mut rx,
+            &mut reader,
+            Framing::Slip,
+            Duration::from_millis(100),
+            max_frame_size,
+        )?;
+        Ok(reader.frame)
+    }
+
+    fn io_error_kind(result: Result<Vec<u8>, Error>) -> io::ErrorKind {
+        match result {
+            Err(Error::Transport(e)) => std::error::Error::source(&e)
+                .and_then(|e| e.downcast_ref::<io::Error>())
+                .unwrap()
+                .kind(),
+            other => panic!("transport error expected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_slip() {
+        let mut data = Vec::new();
+        for payload in PAYLOADS {
+            Framing::Slip.write_frame(payload, &mut data);
+        }
+        assert!(!data[1..data.len() - 1]
+            .windows(2)
+            .any(|w| w[0] == 0xDB && ![0xDC, 0xDD].contains(&w[1])));
+        assert_eq!(read_frames(Framing::Slip, &data), PAYLOADS);
+    }
+
+    #[test]
+    fn test_newline() {
+        let mut data = Vec::new();
+        Framing::Newline.write_frame(PAYLOADS[0], &mut data);
+        // CRLF and empty lines from devices are accepted
+        data.extend(b"\r\n\n[1,2]\r\n");
+        assert_eq!(
+            read_frames(Framing::Newline, &data),
+            [PAYLOADS[0], b"[1,2]"]
+        );
+    }
+
+    #[test]
+    fn test_read_frame() {
+        let mut data = Vec::new();
+        Framing::Slip.write_frame(PAYLOADS[1], &mut data);
+        assert_eq!(read_pipe(&data, false, 1024).unwrap(), PAYLOADS[1]);
+        assert_eq!(
+            io_error_kind(read_pipe(&data[..4], false, 1024)),
+            io::ErrorKind::TimedOut
+        );
+        assert_eq!(
+            io_error_kind(read_pipe(&data[..4], true, 1024)),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert!(matches!(
+            read_pipe(&data, false, 4),
+            Err(Error::Protocol("frame too large"))
+        ));
+    }
+}
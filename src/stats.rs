@@ -0,0 +1,226 @@
+use crate::ErrorKind;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// values are bucketed with 64 linear sub-buckets per power of two, which
+// gives a relative error below 1.6%
+const SUB_BUCKET_BITS: u32 = 6;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+
+/// Latency histogram with microsecond resolution
+#[derive(Default, Debug)]
+struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl Histogram {
+    fn record(&mut self, value: Duration) {
+        let idx = bucket_index(u64::try_from(value.as_micros()).unwrap_or(u64::MAX));
+        if idx >= self.counts.len() {
+            self.counts.resize(idx + 1, 0);
+        }
+        self.counts[idx] += 1;
+        self.total += 1;
+    }
+    fn percentile(&self, p: f64) -> Duration {
+        if self.total == 0 {
+            return Duration::ZERO;
+        }
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        let rank = ((self.total as f64 * p).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (idx, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros(bucket_value(idx));
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS {
+        return usize::try_from(value).unwrap_or_default();
+    }
+    let exp = 63 - value.leading_zeros() - SUB_BUCKET_BITS;
+    let sub = value >> exp;
+    usize::try_from(SUB_BUCKETS * u64::from(exp + 1) + sub - SUB_BUCKETS).unwrap_or(usize::MAX)
+}
+
+// the middle of the bucket
+fn bucket_value(idx: usize) -> u64 {
+    let idx = idx as u64;
+    if idx < SUB_BUCKETS {
+        return idx;
+    }
+    let exp = idx / SUB_BUCKETS - 1;
+    let sub = idx % SUB_BUCKETS + SUB_BUCKETS;
+    (sub << exp) + ((1 << exp) >> 1)
+}
+
+#[derive(Default, Debug)]
+struct Counters {
+    calls: u64,
+    errors: Vec<(ErrorKind, u64)>,
+    latency: Histogram,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+/// Statistics of a method
+#[derive(Debug, Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct MethodStats {
+    pub calls: u64,
+    /// Failed calls by the error kind
+    pub errors: Vec<(ErrorKind, u64)>,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    /// Request payload bytes, including retries
+    pub bytes_sent: u64,
+    /// Response body bytes
+    pub bytes_received: u64,
+}
+
+#[derive(Default, Debug)]
+pub(crate) struct Stats {
+    methods: Mutex<BTreeMap<String, Counters>>,
+}
+
+impl Stats {
+    pub(crate) fn record(
+        &self,
+        method: &str,
+        error: Option<ErrorKind>,
+        latency: Duration,
+        bytes_sent: usize,
+        bytes_received: usize,
+    ) {
+        let mut methods = self.methods.lock().unwrap();
+        let counters = if let Some(counters) = methods.get_mut(method) {
+            counters
+        } else {
+            methods.entry(method.to_owned()).or_default()
+        };
+        counters.calls += 1;
+        counters.latency.record(latency);
+        counters.bytes_sent += bytes_sent as u64;
+        counters.bytes_received += bytes_received as u64;
+        if let Some(kind) = error {
+            if let Some((_, count)) = counters.errors.iter_mut().find(|(k, _)| *k == kind) {
+                *count += 1;
+            } else {
+                counters.errors.push((kind, 1));
+            }
+        }
+    }
+    pub(crate) fn snapshot(&self) -> BTreeMap<String, MethodStats> {
+        self.methods
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(method, counters)| {
+                (
+                    method.clone(),
+                    MethodStats {
+                        calls: counters.calls,
+                        errors: counters.errors.clone(),
+                        p50: counters.latency.percentile(0.5),
+                        p95: counters.latency.percentile(0.95),
+                        p99: counters.latency.percentile(0.99),
+                        bytes_sent: counters.bytes_sent,
+                        bytes_received: counters.bytes_received,
+                    },
+                )
+            })
+            .collect()
+    }
+    pub(crate) fn reset(&self) {
+        self.methods.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bucket_index, bucket_value, Histogram, Stats};
+    use crate::ErrorKind;
+    use std::time::Duration;
+
+    #[test]
+    fn test_buckets() {
+        for value in 0..64 {
+            assert_eq!(bucket_value(bucket_index(value)), value);
+        }
+        let mut prev = bucket_index(63);
+        for shift in 0..58 {
+            for sub in 0..64 {
+                let value = (64 + sub) << shift;
+                let idx = bucket_index(value);
+                // contiguous, a new bucket for every sub-bucket step
+                assert_eq!(idx, prev + 1);
+                prev = idx;
+                for v in [value, value | ((1 << shift) - 1)] {
+                    assert_eq!(bucket_index(v), idx);
+                    #[allow(clippy::cast_precision_loss)]
+                    let error = (bucket_value(idx) as f64 - v as f64).abs() / v as f64;
+                    assert!(error < 0.016, "value {} error {}", v, error);
+                }
+            }
+        }
+        assert_eq!(bucket_index(u64::MAX), prev);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.percentile(0.5), Duration::ZERO);
+        for ms in 1..=100 {
+            histogram.record(Duration::from_millis(ms));
+        }
+        for (p, expected) in [(0.5, 50), (0.95, 95), (0.99, 99), (1.0, 100)] {
+            let value = histogram.percentile(p).as_secs_f64() * 1000.0;
+            assert!((value - f64::from(expected)).abs() / f64::from(expected) < 0.016);
+        }
+        histogram.record(Duration::MAX);
+        assert!(histogram.percentile(1.0) > Duration::from_secs(1 << 40));
+    }
+
+    #[test]
+    fn test_stats() {
+        let stats = Stats::default();
+        stats.record("a", None, Duration::from_millis(10), 100, 200);
+        stats.record(
+            "a",
+            Some(ErrorKind::Timeout),
+            Duration::from_millis(20),
+            100,
+            0,
+        );
+        stats.record(
+            "a",
+            Some(ErrorKind::Timeout),
+            Duration::from_millis(30),
+            100,
+            0,
+        );
+        stats.record("b", Some(ErrorKind::Rpc), Duration::from_millis(1), 10, 20);
+        let snapshot = stats.snapshot();
+        let a = &snapshot["a"];
+        assert_eq!(a.calls, 3);
+        assert_eq!(a.errors, [(ErrorKind::Timeout, 2)]);
+        assert_eq!((a.bytes_sent, a.bytes_received), (300, 200));
+        assert!(a.p50 >= Duration::from_millis(19) && a.p50 <= Duration::from_millis(21));
+        assert!(a.p99 >= Duration::from_millis(29) && a.p99 <= Duration::from_millis(31));
+        assert_eq!(snapshot["b"].errors, [(ErrorKind::Rpc, 1)]);
+        stats.reset();
+        assert!(stats.snapshot().is_empty());
+    }
+}
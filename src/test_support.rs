@@ -0,0 +1,202 @@
+//! Test fixtures: a local HTTP server with scripted replies
+use crate::{Checksum, ChecksumHasher};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A request received by the test server
+pub(crate) struct Request {
+    pub(crate) method: String,
+    /// The path with the query
+    pub(crate) path: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Request {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+    /// The request ID of a JSON body
+    pub(crate) fn id(&self) -> serde_json::Value {
+        serde_json::from_slice::<serde_json::Value>(&self.body)
+            .map(|req| req["id"].clone())
+            .unwrap_or_default()
+    }
+}
+
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Response {
+    pub(crate) fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+    pub(crate) fn json(value: &serde_json::Value) -> Self {
+        Self {
+            status: 200,
+            headers: vec![("content-type".to_owned(), "application/json".to_owned())],
+            body: value.to_string().into_bytes(),
+        }
+    }
+    /// A JSON RPC reply with the ID of the request
+    pub(crate) fn result(req: &Request, result: serde_json::Value) -> Self {
+        Self::json(&serde_json::json!({ "jsonrpc": "2.0", "id": req.id(), "result": result }))
+    }
+    pub(crate) fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+    pub(crate) fn error(req: &Request, code: i16) -> Self {
+        Self::json(&serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": req.id(),
+            "error": { "code": code, "message": "test" }
+        }))
+    }
+}
+
+/// Serves every connection in a thread with the handler, returns the base
+/// URL. Connections are closed after a reply
+pub(crate) fn serve<F>(handler: F) -> String
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let handler = Arc::new(handler);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            let handler = handler.clone();
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let Some(req) = read_request(&mut reader, &mut stream) else {
+                    return;
+                };
+                let resp = handler(&req);
+                let mut head = format!("HTTP/1.1 {} TEST\r\nconnection: close\r\n", resp.status);
+                // handlers may set a false length
+                if !resp
+                    .headers
+                    .iter()
+                    .any(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                {
+                    head.push_str(&format!("content-length: {}\r\n", resp.body.len()));
+                }
+                for (name, value) in resp.headers {
+                    head.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                head.push_str("\r\n");
+                // clients may drop connections, e.g. of cancelled requests
+                let _ = stream
+                    .write_all(head.as_bytes())
+                    .and_then(|()| stream.write_all(&resp.body));
+            });
+        }
+    });
+    url
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Reply {
+    Status(u16),
+    RpcError(i16),
+    /// The result is the number of the request
+    Ok,
+}
+
+/// Replies to JSON RPC requests in order, the last reply is repeated. Returns
+/// the URL and the request counter
+pub(crate) fn serve_replies(replies: Vec<Reply>) -> (String, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let url = serve(move |req| {
+        let n = counter.fetch_add(1, Ordering::SeqCst);
+        match replies[n.min(replies.len() - 1)] {
+            Reply::Status(code) => Response::status(code),
+            Reply::RpcError(code) => Response::error(req, code),
+            Reply::Ok => Response::result(req, n.into()),
+        }
+    });
+    (url, requests)
+}
+
+fn read_request<R: BufRead, W: Write>(reader: &mut R, writer: &mut W) -> Option<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut request_line = line.split_whitespace();
+    let method = request_line.next()?.to_owned();
+    let path = request_line.next()?.to_owned();
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 || line == "\r\n" {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.to_owned(), value.trim().to_owned()));
+        }
+    }
+    let len = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .map_or(0, |(_, value)| value.parse().unwrap());
+    if headers
+        .iter()
+        .any(|(name, value)| name.eq_ignore_ascii_case("expect") && value == "100-continue")
+    {
+        writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").ok()?;
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).ok()?;
+    Some(Request {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+/// A test checksum: the wrapping sum of bytes, hex-encoded
+pub(crate) struct Sum32;
+
+impl Checksum for Sum32 {
+    fn header(&self) -> &'static str {
+        "x-checksum-sum32"
+    }
+    fn compute(&self, body: &[u8]) -> String {
+        let mut hasher = Sum32Hasher(0);
+        hasher.update(body);
+        format!("{:08x}", hasher.0)
+    }
+    fn hasher(&self) -> Option<Box<dyn ChecksumHasher>> {
+        Some(Box::new(Sum32Hasher(0)))
+    }
+}
+
+struct Sum32Hasher(u32);
+
+impl ChecksumHasher for Sum32Hasher {
+    fn update(&mut self, data: &[u8]) {
+        for b in data {
+            self.0 = self.0.wrapping_add(u32::from(*b));
+        }
+    }
+    fn verify(self: Box<Self>, value: &str) -> bool {
+        format!("{:08x}", self.0) == value
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const DEFAULT_WINDOW: usize = 100;
+const DEFAULT_MIN_SAMPLES: usize = 10;
+const DEFAULT_MULTIPLIER: f64 = 2.0;
+const PERCENTILE: f64 = 0.99;
+
+/// Per-method timeouts, computed from the rolling p99 latency of recent calls
+///
+/// The effective timeout is p99 multiplied by the multiplier (2 by default),
+/// bounded by min/max. Until a method has enough samples, the client timeout,
+/// bounded by min/max, is used. Timed out calls are sampled with the latency
+/// of the timeout they had, so the timeout grows back if the method slows
+/// down.
+#[derive(Debug)]
+pub struct AdaptiveTimeout {
+    min: Duration,
+    max: Duration,
+    multiplier: f64,
+    window: usize,
+    min_samples: usize,
+    latencies: Mutex<BTreeMap<String, VecDeque<Duration>>>,
+}
+
+impl AdaptiveTimeout {
+    #[inline]
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max,
+            multiplier: DEFAULT_MULTIPLIER,
+            window: DEFAULT_WINDOW,
+            min_samples: DEFAULT_MIN_SAMPLES,
+            latencies: <_>::default(),
+        }
+    }
+    /// Panics if the multiplier is not a positive finite number
+    #[inline]
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        assert!(
+            multiplier.is_finite() && multiplier > 0.0,
+            "the multiplier must be a positive finite number"
+        );
+        self.multiplier = multiplier;
+        self
+    }
+    /// The number of recent calls the percentile is computed from
+    #[inline]
+    pub fn window(mut self, window: usize) -> Self {
+        self.window = window.max(1);
+        self.min_samples = self.min_samples.min(self.window);
+        self
+    }
+    #[inline]
+    pub(crate) fn bounds(&self) -> (Duration, Duration) {
+        (self.min, self.max)
+    }
+    /// The current timeout for a method
+    pub fn timeout(&self, method: &str, default: Duration) -> Duration {
+        let latencies = self.latencies.lock().unwrap();
+        let timeout = match latencies.get(method) {
+            Some(samples) if samples.len() >= self.min_samples => {
+                let mut sorted: Vec<Duration> = samples.iter().copied().collect();
+                sorted.sort_unstable();
+                #[allow(
+                    clippy::cast_possible_truncation,
+                    clippy::cast_sign_loss,
+                    clippy::cast_precision_loss
+                )]
+                let idx = ((sorted.len() as f64 * PERCENTILE).ceil() as usize).max(1) - 1;
+                Duration::try_from_secs_f64(sorted[idx].as_secs_f64() * self.multiplier)
+                    .unwrap_or(self.max)
+            }
+            _ => default,
+        };
+        timeout.max(self.min).min(self.max)
+    }
+    pub(crate) fn record(&self, method: &str, latency: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        if let Some(samples) = latencies.get_mut(method) {
+            if samples.len() >= self.window {
+                samples.pop_front();
+            }
+            samples.push_back(latency);
+        } else {
+            let mut samples = VecDeque::with_capacity(self.window);
+            samples.push_back(latency);
+            latencies.insert(method.to_owned(), samples);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AdaptiveTimeout;
+    use crate::test_support::{serve, Response};
+    use crate::{http_client, Rpc};
+    use std::time::Duration;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    fn bounded() -> AdaptiveTimeout {
+        AdaptiveTimeout::new(10 * MS, 1000 * MS)
+    }
+
+    #[test]
+    fn test_timeout() {
+        let adaptive = bounded();
+        for _ in 0..9 {
+            adaptive.record("m", 20 * MS);
+        }
+        // not enough samples, the bounded default is used
+        assert_eq!(adaptive.timeout("m", 5000 * MS), 1000 * MS);
+        assert_eq!(adaptive.timeout("m", MS), 10 * MS);
+        adaptive.record("m", 30 * MS);
+        assert_eq!(adaptive.timeout("m", 5000 * MS), 60 * MS);
+        // methods are sampled separately
+        assert_eq!(adaptive.timeout("other", 500 * MS), 500 * MS);
+    }
+
+    #[test]
+    fn test_window() {
+        let adaptive = bounded().window(10).multiplier(1.5);
+        for _ in 0..10 {
+            adaptive.record("m", 100 * MS);
+        }
+        assert_eq!(adaptive.timeout("m", MS), 150 * MS);
+        // old samples are dropped
+        for _ in 0..10 {
+            adaptive.record("m", 10 * MS);
+        }
+        assert_eq!(adaptive.timeout("m", MS), 15 * MS);
+        // too large products are bounded as well
+        let adaptive = bounded().multiplier(1e300).window(1);
+        adaptive.record("m", MS);
+        assert_eq!(adaptive.timeout("m", MS), 1000 * MS);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let adaptive = bounded().multiplier(1.0);
+        for i in 1..=100 {
+            adaptive.record("m", i * MS);
+        }
+        assert_eq!(adaptive.timeout("m", MS), 99 * MS);
+    }
+
+    #[test]
+    #[should_panic(expected = "positive finite")]
+    fn test_multiplier_nan() {
+        let _ = bounded().multiplier(f64::NAN);
+    }
+
+    #[test]
+    #[should_panic(expected = "positive finite")]
+    fn test_multiplier_negative() {
+        let _ = bounded().multiplier(-1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "positive finite")]
+    fn test_multiplier_infinite() {
+        let _ = bounded().multiplier(f64::INFINITY);
+    }
+
+    #[test]
+    fn test_recover_after_timeouts() {
+        let url = serve(|req| {
+            std::thread::sleep(300 * MS);
+            Response::result(req, 1.into())
+        });
+        let client = http_client(&url)
+            .timeout(200 * MS)
+            .adaptive_timeout(AdaptiveTimeout::new(100 * MS, 2000 * MS).window(1));
+        assert!(client.call::<_, u8>("m", ()).unwrap_err().is_timeout());
+        // the timed out call is sampled at 200ms, the timeout is 400ms now
+        assert_eq!(client.call::<_, u8>("m", ()).unwrap(), 1);
+    }
+}
@@ -0,0 +1,188 @@
+//! A runtime-agnostic timer: a single thread serves all sleeps, entries of
+//! dropped futures are removed
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+// deadlines are unique with the sequence number
+type Key = (Instant, u64);
+
+#[derive(Default)]
+struct Timer {
+    state: Mutex<TimerState>,
+    cv: Condvar,
+}
+
+#[derive(Default)]
+struct TimerState {
+    entries: BTreeMap<Key, Waker>,
+    seq: u64,
+}
+
+impl Timer {
+    fn get() -> &'static Timer {
+        static TIMER: OnceLock<Timer> = OnceLock::new();
+        let mut created = false;
+        let timer = TIMER.get_or_init(|| {
+            created = true;
+            Timer::default()
+        });
+        if created {
+            std::thread::Builder::new()
+                .name("bma-jrpc-timer".to_owned())
+                .spawn(move || timer.run())
+                .expect("unable to spawn the timer thread");
+        }
+        timer
+    }
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let mut expired = Vec::new();
+            while let Some(entry) = state.entries.first_entry() {
+                if entry.key().0 > now {
+                    break;
+                }
+                expired.push(entry.remove());
+            }
+            if !expired.is_empty() {
+                drop(state);
+                for waker in expired {
+                    waker.wake();
+                }
+                state = self.state.lock().unwrap();
+                continue;
+            }
+            state = match state.entries.keys().next() {
+                Some(&(deadline, _)) => {
+                    self.cv
+                        .wait_timeout(state, deadline.saturating_duration_since(now))
+                        .unwrap()
+                        .0
+                }
+                None => self.cv.wait(state).unwrap(),
+            };
+        }
+    }
+    fn register(&self, deadline: Instant, key: Option<Key>, waker: &Waker) -> Key {
+        let mut state = self.state.lock().unwrap();
+        let key = key.unwrap_or_else(|| {
+            state.seq += 1;
+            (deadline, state.seq)
+        });
+        let earliest = state.entries.keys().next().is_none_or(|first| key < *first);
+        match state.entries.get_mut(&key) {
+            Some(w) => w.clone_from(waker),
+            None => {
+                state.entries.insert(key, waker.clone());
+            }
+        }
+        if earliest {
+            self.cv.notify_one();
+        }
+        key
+    }
+    fn cancel(&self, key: &Key) {
+        self.state.lock().unwrap().entries.remove(key);
+    }
+}
+
+/// A sleep future, the timer entry is removed when the future is dropped
+pub(crate) struct Delay {
+    // none if the deadline is out of the clock range, such delays never end
+    deadline: Option<Instant>,
+    key: Option<Key>,
+}
+
+impl Delay {
+    pub(crate) fn new(duration: Duration) -> Self {
+        Self {
+            deadline: Instant::now().checked_add(duration),
+            key: None,
+        }
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let Some(deadline) = self.deadline else {
+            return Poll::Pending;
+        };
+        if Instant::now() >= deadline {
+            if let Some(key) = self.key.take() {
+                Timer::get().cancel(&key);
+            }
+            return Poll::Ready(());
+        }
+        let key = Timer::get().register(deadline, self.key, cx.waker());
+        self.key = Some(key);
+        Poll::Pending
+    }
+}
+
+impl Drop for Delay {
+    fn drop(&mut self) {
+        if let Some(ref key) = self.key {
+            Timer::get().cancel(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Delay, Timer};
+    use futures_lite::future;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_delay() {
+        let started = Instant::now();
+        future::block_on(Delay::new(Duration::from_millis(50)));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        future::block_on(Delay::new(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_delay_drop() {
+        let delays: Vec<_> = (0..50)
+            .map(|_| {
+                let mut delay = Box::pin(Delay::new(Duration::from_secs(3600)));
+                assert!(future::block_on(future::poll_once(&mut delay)).is_none());
+                delay
+            })
+            .collect();
+        let key = delays[0].key.unwrap();
+        let registered = || {
+            Timer::get()
+                .state
+                .lock()
+                .unwrap()
+                .entries
+                .contains_key(&key)
+        };
+        assert!(registered());
+        drop(delays);
+        assert!(!registered());
+    }
+
+    #[test]
+    fn test_delay_order() {
+        let started = Instant::now();
+        let long = Delay::new(Duration::from_secs(3600));
+        future::block_on(future::or(long, Delay::new(Duration::from_millis(20))));
+        assert!(started.elapsed() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_delay_overflow() {
+        let mut delay = Delay::new(Duration::MAX);
+        assert!(future::block_on(future::poll_once(&mut delay)).is_none());
+        assert!(delay.key.is_none());
+        future::block_on(future::or(delay, Delay::new(Duration::from_millis(1))));
+    }
+}
@@ -0,0 +1,471 @@
+//! Serde wrappers for durations and timestamps in params and results
+//!
+//! ```rust
+//! use bma_jrpc::types::{FloatSeconds, Rfc3339};
+//! use serde::{Deserialize, Serialize};
+//! use std::time::Duration;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Params {
+//!     timeout: FloatSeconds,
+//!     since: Rfc3339,
+//! }
+//!
+//! let params = Params {
+//!     timeout: Duration::from_millis(1500).into(),
+//!     since: "2024-05-01T12:00:00Z".parse().unwrap(),
+//! };
+//! assert_eq!(
+//!     serde_json::to_string(&params).unwrap(),
+//!     r#"{"timeout":1.5,"since":"2024-05-01T12:00:00Z"}"#
+//! );
+//! ```
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECS_PER_DAY: i64 = 86_400;
+
+/// A duration as float seconds
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct FloatSeconds(pub Duration);
+
+/// A duration as integer milliseconds
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Millis(pub Duration);
+
+/// A timestamp as float seconds since the UNIX epoch
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct EpochSeconds(pub SystemTime);
+
+/// A timestamp as integer milliseconds since the UNIX epoch
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct EpochMillis(pub SystemTime);
+
+/// A timestamp as an RFC 3339 string, formatted in UTC. Parsing accepts any
+/// offset
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Rfc3339(pub SystemTime);
+
+macro_rules! impl_from {
+    ($t: ty, $inner: ty) => {
+        impl From<$inner> for $t {
+            #[inline]
+            fn from(v: $inner) -> Self {
+                Self(v)
+            }
+        }
+        impl From<$t> for $inner {
+            #[inline]
+            fn from(v: $t) -> Self {
+                v.0
+            }
+        }
+    };
+}
+
+impl_from!(FloatSeconds, Duration);
+impl_from!(Millis, Duration);
+impl_from!(EpochSeconds, SystemTime);
+impl_from!(EpochMillis, SystemTime);
+impl_from!(Rfc3339, SystemTime);
+
+// timestamps before the epoch are negative
+fn to_epoch_secs(t: SystemTime) -> f64 {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    }
+}
+
+fn from_epoch_secs(secs: f64) -> Option<SystemTime> {
+    let d = Duration::try_from_secs_f64(secs.abs()).ok()?;
+    if secs < 0.0 {
+        UNIX_EPOCH.checked_sub(d)
+    } else {
+        UNIX_EPOCH.checked_add(d)
+    }
+}
+
+// the float visitor accepts integers as well
+struct FloatVisitor;
+
+impl Visitor<'_> for FloatVisitor {
+    type Value = f64;
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a number")
+    }
+    #[inline]
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<f64, E> {
+        Ok(v)
+    }
+    #[allow(clippy::cast_precision_loss)]
+    #[inline]
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<f64, E> {
+        Ok(v as f64)
+    }
+    #[allow(clippy::cast_precision_loss)]
+    #[inline]
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<f64, E> {
+        Ok(v as f64)
+    }
+}
+
+impl Serialize for FloatSeconds {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.0.as_secs_f64())
+    }
+}
+
+impl<'de> Deserialize<'de> for FloatSeconds {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let secs = deserializer.deserialize_f64(FloatVisitor)?;
+        Duration::try_from_secs_f64(secs)
+            .map(Self)
+            .map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Millis {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let millis = u64::try_from(self.0.as_millis()).map_err(serde::ser::Error::custom)?;
+        serializer.serialize_u64(millis)
+    }
+}
+
+impl<'de> Deserialize<'de> for Millis {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u64::deserialize(deserializer).map(|millis| Self(Duration::from_millis(millis)))
+    }
+}
+
+impl Serialize for EpochSeconds {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(to_epoch_secs(self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for EpochSeconds {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let secs = deserializer.deserialize_f64(FloatVisitor)?;
+        from_epoch_secs(secs)
+            .map(Self)
+            .ok_or_else(|| de::Error::custom("timestamp out of range"))
+    }
+}
+
+impl Serialize for EpochMillis {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let millis = match self.0.duration_since(UNIX_EPOCH) {
+            Ok(d) => i64::try_from(d.as_millis()),
+            Err(e) => i64::try_from(e.duration().as_millis()).map(|m| -m),
+        }
+        .map_err(serde::ser::Error::custom)?;
+        serializer.serialize_i64(millis)
+    }
+}
+
+impl<'de> Deserialize<'de> for EpochMillis {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let millis = i64::deserialize(deserializer)?;
+        let d = Duration::from_millis(millis.unsigned_abs());
+        if millis < 0 {
+            UNIX_EPOCH.checked_sub(d)
+        } else {
+            UNIX_EPOCH.checked_add(d)
+        }
+        .map(Self)
+        .ok_or_else(|| de::Error::custom("timestamp out of range"))
+    }
+}
+
+impl fmt::Display for Rfc3339 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (secs, nanos) = match self.0.duration_since(UNIX_EPOCH) {
+            Ok(d) => (
+                i64::try_from(d.as_secs()).map_err(|_| fmt::Error)?,
+                d.subsec_nanos(),
+            ),
+            Err(e) => {
+                let d = e.duration();
+                let secs = i64::try_from(d.as_secs()).map_err(|_| fmt::Error)?;
+                if d.subsec_nanos() == 0 {
+                    (-secs, 0)
+                } else {
+                    (-secs - 1, 1_000_000_000 - d.subsec_nanos())
+                }
+            }
+        };
+        let (year, month, day) = civil_from_days(secs.div_euclid(SECS_PER_DAY));
+        let time = secs.rem_euclid(SECS_PER_DAY);
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            time / 3600,
+            time % 3600 / 60,
+            time % 60
+        )?;
+        if nanos > 0 {
+            let fraction = format!("{:09}", nanos);
+            write!(f, ".{}", fraction.trim_end_matches('0'))?;
+        }
+        f.write_str("Z")
+    }
+}
+
+impl FromStr for Rfc3339 {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const INVALID: &str = "invalid RFC 3339 timestamp";
+        let b = s.as_bytes();
+        let num = |range: std::ops::Range<usize>| -> Result<i64, &'static str> {
+            let digits = b.get(range).ok_or(INVALID)?;
+            if !digits.iter().all(u8::is_ascii_digit) {
+                return Err(INVALID);
+            }
+            Ok(digits.iter().fold(0, |n, d| n * 10 + i64::from(d - b'0')))
+        };
+        if b.len() < 20
+            || b[4] != b'-'
+            || b[7] != b'-'
+            || !matches!(b[10], b'T' | b't' | b' ')
+            || b[13] != b':'
+            || b[16] != b':'
+        {
+            return Err(INVALID);
+        }
+        let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+        let (hour, minute, second) = (num(11..13)?, num(14..16)?, num(17..19)?);
+        if !(1..=12).contains(&month)
+            || day < 1
+            || day > days_in_month(year, month)
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return Err(INVALID);
+        }
+        let mut pos = 19;
+        let mut nanos = 0;
+        if b[pos] == b'.' {
+            pos += 1;
+            let start = pos;
+            while pos < b.len() && b[pos].is_ascii_digit() {
+                // digits beyond nanoseconds are ignored
+                if pos - start < 9 {
+                    nanos = nanos * 10 + i64::from(b[pos] - b'0');
+                }
+                pos += 1;
+            }
+            if pos == start {
+                return Err(INVALID);
+            }
+            for _ in (pos - start)..9 {
+                nanos *= 10;
+            }
+        }
+        let offset = match b.get(pos..).ok_or(INVALID)? {
+            b"Z" | b"z" => 0,
+            [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+                let (h, m) = (num(pos + 1..pos + 3)?, num(pos + 4..pos + 6)?);
+                if h > 23 || m > 59 {
+                    return Err(INVALID);
+                }
+                let offset = h * 3600 + m * 60;
+                if *sign == b'-' {
+                    -offset
+                } else {
+                    offset
+                }
+            }
+            _ => return Err(INVALID),
+        };
+        let secs =
+            days_from_civil(year, month, day) * SECS_PER_DAY + hour * 3600 + minute * 60 + second
+                - offset;
+        let nanos = u32::try_from(nanos).map_err(|_| INVALID)?;
+        let time = if secs < 0 {
+            UNIX_EPOCH
+                .checked_sub(Duration::from_secs(secs.unsigned_abs()))
+                .and_then(|t| t.checked_add(Duration::from_nanos(u64::from(nanos))))
+        } else {
+            UNIX_EPOCH.checked_add(Duration::new(secs.unsigned_abs(), nanos))
+        };
+        time.map(Self).ok_or("timestamp out of range")
+    }
+}
+
+impl Serialize for Rfc3339 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rfc3339 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// days since 1970-01-01 of a proleptic Gregorian date (H. Hinnant's algorithm)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EpochMillis, EpochSeconds, FloatSeconds, Millis, Rfc3339};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn at(secs: i64, nanos: u32) -> SystemTime {
+        if secs < 0 {
+            UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+                + Duration::from_nanos(nanos.into())
+        } else {
+            UNIX_EPOCH + Duration::new(secs.unsigned_abs(), nanos)
+        }
+    }
+
+    fn parse(s: &str) -> SystemTime {
+        s.parse::<Rfc3339>().unwrap().0
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        for (s, secs, nanos) in [
+            ("1970-01-01T00:00:00Z", 0, 0),
+            ("2024-05-01T12:00:00Z", 1_714_564_800, 0),
+            ("2024-05-01T12:00:00.25Z", 1_714_564_800, 250_000_000),
+            ("2024-05-01T12:00:00.000000001Z", 1_714_564_800, 1),
+            ("1969-12-31T23:59:59Z", -1, 0),
+            ("1969-12-31T23:59:59.5Z", -1, 500_000_000),
+            ("1900-03-01T00:00:00.123Z", -2_203_891_200, 123_000_000),
+        ] {
+            assert_eq!(parse(s), at(secs, nanos), "{}", s);
+            assert_eq!(Rfc3339(at(secs, nanos)).to_string(), s);
+        }
+        // digits beyond nanoseconds are ignored
+        assert_eq!(
+            parse("2024-05-01T12:00:00.1234567899Z"),
+            at(1_714_564_800, 123_456_789)
+        );
+        assert_eq!(parse("2024-05-01t12:00:00z"), at(1_714_564_800, 0));
+        assert_eq!(parse("2024-05-01 12:00:00Z"), at(1_714_564_800, 0));
+    }
+
+    #[test]
+    fn test_rfc3339_offsets() {
+        let t = at(1_714_564_800, 0);
+        assert_eq!(parse("2024-05-01T14:30:00+02:30"), t);
+        assert_eq!(parse("2024-05-01T07:00:00-05:00"), t);
+        assert_eq!(parse("2024-05-01T12:00:00+00:00"), t);
+        assert_eq!(parse("2024-05-01T12:00:00-00:00"), t);
+        // the offset moves the date
+        assert_eq!(parse("2024-04-30T23:00:00-13:00"), t);
+        assert_eq!(parse("1970-01-01T00:00:00.5+01:00"), at(-3600, 500_000_000));
+        assert_eq!(Rfc3339(t).to_string(), "2024-05-01T12:00:00Z");
+        for s in [
+            "2024-05-01T12:00:00+24:00",
+            "2024-05-01T12:00:00+02:60",
+            "2024-05-01T12:00:00+0200",
+            "2024-05-01T12:00:00+02",
+            "2024-05-01T12:00:00",
+            "2024-05-01T12:00:00Z+01:00",
+        ] {
+            assert!(s.parse::<Rfc3339>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_rfc3339_leap_days() {
+        for (s, secs) in [
+            ("2000-02-29T00:00:00Z", 951_782_400),
+            ("2000-03-01T00:00:00Z", 951_782_400 + 86_400),
+            ("2000-02-28T00:00:00Z", 951_782_400 - 86_400),
+            ("1968-02-29T12:00:00Z", -58_017_600),
+        ] {
+            assert_eq!(parse(s), at(secs, 0), "{}", s);
+            assert_eq!(Rfc3339(at(secs, 0)).to_string(), s);
+        }
+        for s in [
+            "2023-02-29T00:00:00Z",
+            "1900-02-29T00:00:00Z",
+            "2024-02-30T00:00:00Z",
+            "2024-04-31T00:00:00Z",
+            "2024-13-01T00:00:00Z",
+            "2024-00-01T00:00:00Z",
+            "2024-05-01T24:00:00Z",
+            "2024-05-01T12:00:00.Z",
+            "2024-05-01",
+        ] {
+            assert!(s.parse::<Rfc3339>().is_err(), "{}", s);
+        }
+        // a leap second is the first second of the next minute
+        assert_eq!(parse("2016-12-31T23:59:60Z"), parse("2017-01-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn test_epoch() {
+        let t = at(-2, 500_000_000);
+        assert_eq!(serde_json::to_string(&EpochSeconds(t)).unwrap(), "-1.5");
+        let EpochSeconds(parsed) = serde_json::from_str("-1.5").unwrap();
+        assert_eq!(parsed, t);
+        let EpochSeconds(parsed) = serde_json::from_str("-2").unwrap();
+        assert_eq!(parsed, at(-2, 0));
+        assert_eq!(serde_json::to_string(&EpochMillis(t)).unwrap(), "-1500");
+        let EpochMillis(parsed) = serde_json::from_str("-1500").unwrap();
+        assert_eq!(parsed, t);
+        let EpochMillis(parsed) = serde_json::from_str("1714564800250").unwrap();
+        assert_eq!(parsed, at(1_714_564_800, 250_000_000));
+    }
+
+    #[test]
+    fn test_durations() {
+        let d = Duration::from_millis(1500);
+        assert_eq!(serde_json::to_string(&FloatSeconds(d)).unwrap(), "1.5");
+        assert_eq!(serde_json::to_string(&Millis(d)).unwrap(), "1500");
+        let FloatSeconds(parsed) = serde_json::from_str("2").unwrap();
+        assert_eq!(parsed, Duration::from_secs(2));
+        assert!(serde_json::from_str::<FloatSeconds>("-1.5").is_err());
+        assert!(serde_json::from_str::<Millis>("-1").is_err());
+    }
+}